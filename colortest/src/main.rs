@@ -105,8 +105,8 @@ fn find_shift_add(range: RangeInclusive<u16>, lut: &[u8; 512]) -> ShiftAdd {
 
     for i in 0..=18 {
         for a0 in [true, false] {
-            let lshift0 = if i <= 9 { 9 - i } else { 0 };
-            let rshift0 = if i > 9 { i - 9 } else { 0 };
+            let lshift0 = 9u16.saturating_sub(i);
+            let rshift0 = i.saturating_sub(9);
             let term0 = Term {
                 lsh: lshift0,
                 rsh: rshift0,
@@ -118,8 +118,8 @@ fn find_shift_add(range: RangeInclusive<u16>, lut: &[u8; 512]) -> ShiftAdd {
                     continue;
                 }
                 for a1 in [true, false] {
-                    let lshift1 = if j <= 9 { 9 - j } else { 0 };
-                    let rshift1 = if j > 9 { j - 9 } else { 0 };
+                    let lshift1 = 9u16.saturating_sub(j);
+                    let rshift1 = j.saturating_sub(9);
                     let term1 = Term {
                         lsh: lshift1,
                         rsh: rshift1,
@@ -131,8 +131,8 @@ fn find_shift_add(range: RangeInclusive<u16>, lut: &[u8; 512]) -> ShiftAdd {
                             continue;
                         }
                         for a2 in [true, false] {
-                            let lshift2 = if k <= 9 { 9 - k } else { 0 };
-                            let rshift2 = if k > 9 { k - 9 } else { 0 };
+                            let lshift2 = 9u16.saturating_sub(k);
+                            let rshift2 = k.saturating_sub(9);
                             let term2 = Term {
                                 lsh: lshift2,
                                 rsh: rshift2,
@@ -237,10 +237,7 @@ fn main() {
             best_shift_add2.print();
             best_shift_add3.print();
             println!("0 }}");
-            println!(
-                "Best error: {} diff: {} -> {}",
-                min_error, min_diff, min_max_diff
-            );
+            println!("Best error: {min_error} diff: {min_diff} -> {min_max_diff}");
         }
     }
 
@@ -248,7 +245,7 @@ fn main() {
     best_shift_add2.print();
     best_shift_add3.print();
     println!("0 }}");
-    println!("Best error: {} max diff: {}", min_error, min_max_diff);
+    println!("Best error: {min_error} max diff: {min_max_diff}");
 
     min_error = f32::MAX;
     min_max_diff = 0;
@@ -256,7 +253,7 @@ fn main() {
     let fixed_b2 = best_shift_add2.br;
 
     for b1 in 10..=128u16 {
-        println!("b1: {}", b1);
+        println!("b1: {b1}");
         for b2 in (fixed_b2)..=(fixed_b2 + 30) {
             let shift_add1 = find_shift_add(1..=b1, &lut);
             let shift_add2 = find_shift_add(b1..=b2, &lut);
@@ -299,10 +296,7 @@ fn main() {
                 best_shift_add2.print();
                 best_shift_add3.print();
                 println!("0 }}");
-                println!(
-                    "Best error: {} diff: {} -> {}",
-                    min_error, min_diff, min_max_diff
-                );
+                println!("Best error: {min_error} diff: {min_diff} -> {min_max_diff}");
             }
         }
     }
@@ -312,7 +306,7 @@ fn main() {
     best_shift_add2.print();
     best_shift_add3.print();
     println!("0 }}");
-    println!("Best error: {} max diff: {}", min_error, min_max_diff);
+    println!("Best error: {min_error} max diff: {min_max_diff}");
 
     min_error = f32::MAX;
     min_max_diff = 0;
@@ -322,7 +316,7 @@ fn main() {
 
     for b0 in 8..=64u16 {
         for b1 in (fixed_b1)..=(fixed_b1 + 20) {
-            println!("b0: {}, b1: {}", b0, b1);
+            println!("b0: {b0}, b1: {b1}");
             for b2 in (fixed_b2)..=(fixed_b2 + 30) {
                 let shift_add0 = find_shift_add(1..=b0, &lut);
                 let shift_add1 = find_shift_add(b0..=b1, &lut);
@@ -370,10 +364,7 @@ fn main() {
                     best_shift_add2.print();
                     best_shift_add3.print();
                     println!("0 }}");
-                    println!(
-                        "Best error: {} diff: {} -> {}",
-                        min_error, min_diff, min_max_diff
-                    );
+                    println!("Best error: {min_error} diff: {min_diff} -> {min_max_diff}");
                 }
             }
         }
@@ -385,7 +376,7 @@ fn main() {
     best_shift_add2.print();
     best_shift_add3.print();
     println!("0 }}");
-    println!("Best error: {} max diff: {}", min_error, min_max_diff);
+    println!("Best error: {min_error} max diff: {min_max_diff}");
 
     // for i in 0..=255 {
     //     let x = srgb2linear(i);
@@ -0,0 +1,341 @@
+//! Binary asset files
+//!
+//! These hold the same words as the hex files, little-endian, so they can be
+//! loaded into SDRAM or flash at run time, copied by a CPU or DMA, or loaded
+//! by the emulator without parsing text. A file starts with a header:
+//!
+//! | Offset | Size | Field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 4    | Magic, `VDPA`                          |
+//! | 4      | 2    | Format version, 1                      |
+//! | 6      | 2    | Header size in bytes                   |
+//! | 8      | 2    | Tile width in pixels                   |
+//! | 10     | 2    | Tile height in pixels                  |
+//! | 12     | 2    | Tilemap width in tiles                 |
+//! | 14     | 2    | Tilemap height in tiles                |
+//! | 16     | 2    | Number of palettes                     |
+//! | 18     | 2    | Colors per palette                     |
+//! | 20     | 2    | Tile slots in the tiles section        |
+//! | 22     | 2    | Unique tiles used                      |
+//! | 24     | 4    | Palette words                          |
+//! | 28     | 4    | Tile words                             |
+//! | 32     | 4    | Tilemap words                          |
+//! | 36     | 4    | Mask words, 0 without transparency     |
+//!
+//! The sections follow in that order: 32 bit `0x00rrggbb` palette colors,
+//! then 16 bit texel words laid out like tiles.hex, 16 bit tilemap entries,
+//! and 16 bit transparency mask words laid out like the mask hex file.
+//! Readers should skip to the header size rather than assume it, so later
+//! versions can add fields.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::imgconv::{ConversionError, TilemapData};
+use crate::output;
+
+/// First bytes of a binary asset file
+pub const MAGIC: [u8; 4] = *b"VDPA";
+/// Format version this writes, and the only one it reads
+pub const VERSION: u16 = 1;
+/// Size of the version 1 header in bytes
+pub const HEADER_SIZE: usize = 40;
+
+/// Dimensions and section sizes from the header of a binary asset file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AssetHeader {
+    /// Format version
+    pub version: u16,
+    /// Tile width in pixels
+    pub tile_width: u16,
+    /// Tile height in pixels
+    pub tile_height: u16,
+    /// Tilemap width in tiles
+    pub tilemap_width: u16,
+    /// Tilemap height in tiles
+    pub tilemap_height: u16,
+    /// Number of palettes
+    pub palettes: u16,
+    /// Colors per palette
+    pub colors_per_palette: u16,
+    /// Tile slots in the tiles section, used or not
+    pub tile_slots: u16,
+    /// Unique tiles the tilemap uses
+    pub unique_tiles: u16,
+    /// Number of words in the palette section
+    pub palette_words: u32,
+    /// Number of words in the tiles section
+    pub tile_words: u32,
+    /// Number of words in the tilemap section
+    pub tilemap_words: u32,
+    /// Number of words in the mask section
+    pub mask_words: u32,
+}
+
+/// The header and sections of a binary asset file
+#[derive(Debug, Clone)]
+pub struct BinaryAssets {
+    pub header: AssetHeader,
+    /// 24 bit `rrggbb` colours
+    pub palette: Vec<u32>,
+    /// Texel words, row-interleaved like tiles.hex
+    pub tiles: Vec<u16>,
+    /// `palette << 10 | tile` entries
+    pub tilemap: Vec<u16>,
+    /// Transparency mask words, empty without transparency
+    pub mask: Vec<u16>,
+}
+
+impl BinaryAssets {
+    /// Gather the words of a conversion
+    pub fn new(data: &TilemapData) -> Self {
+        let config = &data.config;
+        let palette = output::palette_words(data);
+        let tiles = output::tile_words(data);
+        let tilemap = output::tilemap_words(data);
+        let mask = if config.transparency {
+            output::mask_words(data)
+        } else {
+            Vec::new()
+        };
+
+        BinaryAssets {
+            header: AssetHeader {
+                version: VERSION,
+                tile_width: config.tile_width as u16,
+                tile_height: config.tile_height as u16,
+                tilemap_width: config.tilemap_width as u16,
+                tilemap_height: config.tilemap_height as u16,
+                palettes: data.palettes.len() as u16,
+                colors_per_palette: config.colors_per_palette as u16,
+                tile_slots: data.unique_tiles.len().max(config.max_unique_tiles) as u16,
+                unique_tiles: data.unique_tiles.len() as u16,
+                palette_words: palette.len() as u32,
+                tile_words: tiles.len() as u32,
+                tilemap_words: tilemap.len() as u32,
+                mask_words: mask.len() as u32,
+            },
+            palette,
+            tiles,
+            tilemap,
+            mask,
+        }
+    }
+
+    /// Encode the header and sections
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = &self.header;
+        let mut bytes = Vec::with_capacity(
+            HEADER_SIZE
+                + self.palette.len() * 4
+                + (self.tiles.len() + self.tilemap.len() + self.mask.len()) * 2,
+        );
+
+        bytes.extend(MAGIC);
+        for field in [
+            header.version,
+            HEADER_SIZE as u16,
+            header.tile_width,
+            header.tile_height,
+            header.tilemap_width,
+            header.tilemap_height,
+            header.palettes,
+            header.colors_per_palette,
+            header.tile_slots,
+            header.unique_tiles,
+        ] {
+            bytes.extend(field.to_le_bytes());
+        }
+        for field in [
+            self.palette.len(),
+            self.tiles.len(),
+            self.tilemap.len(),
+            self.mask.len(),
+        ] {
+            bytes.extend((field as u32).to_le_bytes());
+        }
+
+        bytes.extend(self.palette.iter().flat_map(|word| word.to_le_bytes()));
+        for section in [&self.tiles, &self.tilemap, &self.mask] {
+            bytes.extend(section.iter().flat_map(|word| word.to_le_bytes()));
+        }
+        bytes
+    }
+
+    /// Decode a binary asset file
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err("not a binary asset file".to_string());
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(format!("version {version} is not supported"));
+        }
+        let header_size = reader.u16()? as usize;
+        if header_size < HEADER_SIZE {
+            return Err(format!(
+                "header is {header_size} bytes, less than {HEADER_SIZE}"
+            ));
+        }
+
+        let header = AssetHeader {
+            version,
+            tile_width: reader.u16()?,
+            tile_height: reader.u16()?,
+            tilemap_width: reader.u16()?,
+            tilemap_height: reader.u16()?,
+            palettes: reader.u16()?,
+            colors_per_palette: reader.u16()?,
+            tile_slots: reader.u16()?,
+            unique_tiles: reader.u16()?,
+            palette_words: reader.u32()?,
+            tile_words: reader.u32()?,
+            tilemap_words: reader.u32()?,
+            mask_words: reader.u32()?,
+        };
+
+        reader.offset = header_size;
+        let palette = reader.words(header.palette_words, u32::from_le_bytes)?;
+        let tiles = reader.words(header.tile_words, u16::from_le_bytes)?;
+        let tilemap = reader.words(header.tilemap_words, u16::from_le_bytes)?;
+        let mask = reader.words(header.mask_words, u16::from_le_bytes)?;
+
+        Ok(BinaryAssets {
+            header,
+            palette,
+            tiles,
+            tilemap,
+            mask,
+        })
+    }
+}
+
+/// Read a binary asset file
+pub fn read(path: impl AsRef<Path>) -> Result<BinaryAssets, ConversionError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+    BinaryAssets::parse(&bytes)
+        .map_err(|e| ConversionError::InvalidBinary(format!("{}: {e}", path.display())))
+}
+
+/// Little-endian values from the bytes of a file, in order
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| format!("file ends before byte {}", self.offset + len))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A section of `count` words
+    fn words<const N: usize, T>(
+        &mut self,
+        count: u32,
+        from_le_bytes: fn([u8; N]) -> T,
+    ) -> Result<Vec<T>, String> {
+        let bytes = self.take(count as usize * N)?;
+        Ok(bytes
+            .chunks_exact(N)
+            .map(|word| from_le_bytes(word.try_into().unwrap()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assets() -> BinaryAssets {
+        let (palette, tiles, tilemap, mask) = (
+            vec![0x00ff_8000, 0x0012_3456],
+            vec![0x1234, 0xabcd, 0x0f0f],
+            vec![3 << 10 | 2],
+            vec![0xffff],
+        );
+        BinaryAssets {
+            header: AssetHeader {
+                version: VERSION,
+                tile_width: 8,
+                tile_height: 8,
+                tilemap_width: 32,
+                tilemap_height: 1,
+                palettes: 1,
+                colors_per_palette: 2,
+                tile_slots: 256,
+                unique_tiles: 3,
+                palette_words: palette.len() as u32,
+                tile_words: tiles.len() as u32,
+                tilemap_words: tilemap.len() as u32,
+                mask_words: mask.len() as u32,
+            },
+            palette,
+            tiles,
+            tilemap,
+            mask,
+        }
+    }
+
+    #[test]
+    fn header_fields_sit_at_their_documented_offsets() {
+        let bytes = assets().to_bytes();
+        assert_eq!(&bytes[0..4], b"VDPA");
+        assert_eq!(bytes[4..6], 1u16.to_le_bytes());
+        assert_eq!(bytes[6..8], (HEADER_SIZE as u16).to_le_bytes());
+        assert_eq!(bytes[12..14], 32u16.to_le_bytes());
+        assert_eq!(bytes[24..28], 2u32.to_le_bytes());
+        assert_eq!(bytes[36..40], 1u32.to_le_bytes());
+        assert_eq!(bytes[40..44], 0x00ff_8000u32.to_le_bytes());
+        assert_eq!(bytes.len(), HEADER_SIZE + 2 * 4 + 5 * 2);
+    }
+
+    #[test]
+    fn assets_read_back_as_written() {
+        let assets = assets();
+        let parsed = BinaryAssets::parse(&assets.to_bytes()).unwrap();
+        assert_eq!(parsed.header, assets.header);
+        assert_eq!(parsed.palette, assets.palette);
+        assert_eq!(parsed.tiles, assets.tiles);
+        assert_eq!(parsed.tilemap, assets.tilemap);
+        assert_eq!(parsed.mask, assets.mask);
+    }
+
+    #[test]
+    fn longer_headers_are_skipped() {
+        let mut bytes = assets().to_bytes();
+        bytes.splice(HEADER_SIZE..HEADER_SIZE, [0xee; 8]);
+        bytes[6..8].copy_from_slice(&(HEADER_SIZE as u16 + 8).to_le_bytes());
+        let parsed = BinaryAssets::parse(&bytes).unwrap();
+        assert_eq!(parsed.palette, assets().palette);
+        assert_eq!(parsed.mask, assets().mask);
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        let bytes = assets().to_bytes();
+        assert!(BinaryAssets::parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(BinaryAssets::parse(b"VDPB").is_err());
+
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert!(BinaryAssets::parse(&newer).is_err());
+    }
+}
@@ -4,9 +4,12 @@
 //! - `Oklab` wrapper for the oklab crate's color type
 //! - Color distance calculations
 //! - Color frequency counting
+//! - Reducing colors to the hardware's color depth
 
+use std::fmt;
 use std::simd::num::SimdFloat;
 use std::simd::{LaneCount, Simd, StdFloat, SupportedLaneCount};
+use std::str::FromStr;
 
 use kmeans::DistanceFunction;
 use oklab::{self, oklab_to_srgb, srgb_to_oklab, Rgb};
@@ -99,6 +102,118 @@ impl Oklab {
     }
 }
 
+/// Bits per channel the palette RAM stores
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ColorDepth {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Default for ColorDepth {
+    fn default() -> Self {
+        ColorDepth {
+            red: 8,
+            green: 8,
+            blue: 8,
+        }
+    }
+}
+
+impl ColorDepth {
+    /// Whether colors are stored with all 8 bits per channel, so need no reducing
+    pub fn is_full(self) -> bool {
+        self == ColorDepth::default()
+    }
+
+    /// Reduce an 8 bit channel to the given number of bits, rounding to the
+    /// nearest level, then widen it back by repeating the bits so the top bits
+    /// are the value the hardware stores
+    fn reduce_channel(value: u8, bits: u8) -> u8 {
+        let levels = (1u32 << bits) - 1;
+        let level = (value as u32 * levels + 127) / 255;
+        let mut widened = 0;
+        let mut shift = 8i32 - bits as i32;
+        while shift > -(bits as i32) {
+            widened |= if shift >= 0 {
+                level << shift
+            } else {
+                level >> -shift
+            };
+            shift -= bits as i32;
+        }
+        widened as u8
+    }
+
+    /// Reduce an RGB color to this depth
+    pub fn reduce_rgb(self, r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+        (
+            Self::reduce_channel(r, self.red),
+            Self::reduce_channel(g, self.green),
+            Self::reduce_channel(b, self.blue),
+        )
+    }
+
+    /// Whether the hardware can show an RGB color exactly at this depth
+    pub fn represents(self, r: u8, g: u8, b: u8) -> bool {
+        self.reduce_rgb(r, g, b) == (r, g, b)
+    }
+
+    /// Snap a color to the nearest one the hardware can show at this depth
+    pub fn reduce(self, color: Oklab) -> Oklab {
+        if self.is_full() {
+            return color;
+        }
+        let (r, g, b) = color.to_rgb();
+        let (r, g, b) = self.reduce_rgb(r, g, b);
+        Oklab::from_rgb(r, g, b)
+    }
+}
+
+impl FromStr for ColorDepth {
+    type Err = String;
+
+    /// Parse three digits giving the bits for red, green and blue, like `565`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bits: Vec<u8> = s
+            .strip_prefix("rgb")
+            .unwrap_or(s)
+            .chars()
+            .map(|c| c.to_digit(10).map(|d| d as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("{s} is not a color depth like 444, 555 or 565"))?;
+        match bits[..] {
+            [red, green, blue] if [red, green, blue].iter().all(|b| (1..=8).contains(b)) => {
+                Ok(ColorDepth { red, green, blue })
+            }
+            _ => Err(format!(
+                "{s} is not a color depth like 444, 555 or 565, with 1 to 8 bits per channel"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for ColorDepth {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ColorDepth> for String {
+    fn from(depth: ColorDepth) -> Self {
+        depth.to_string()
+    }
+}
+
+impl fmt::Display for ColorDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.red, self.green, self.blue)
+    }
+}
+
 /// A color and its frequency in an image
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct ColorFrequency {
@@ -215,3 +330,64 @@ pub fn find_similar_color(
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_round_to_the_nearest_level_and_repeat_their_bits() {
+        assert_eq!(ColorDepth::reduce_channel(0x12, 4), 0x11);
+        assert_eq!(ColorDepth::reduce_channel(0x88, 4), 0x88);
+        assert_eq!(ColorDepth::reduce_channel(0xf7, 4), 0xff);
+        assert_eq!(ColorDepth::reduce_channel(0x40, 5), 0x42);
+        assert_eq!(ColorDepth::reduce_channel(0x7f, 1), 0x00);
+        assert_eq!(ColorDepth::reduce_channel(0x80, 1), 0xff);
+    }
+
+    #[test]
+    fn reduced_channels_are_stored_in_the_top_bits() {
+        for bits in 1..=8u8 {
+            for value in 0..=255u8 {
+                let reduced = ColorDepth::reduce_channel(value, bits);
+                let level = (value as u32 * ((1 << bits) - 1) + 127) / 255;
+                assert_eq!(
+                    (reduced >> (8 - bits)) as u32,
+                    level,
+                    "{value} at {bits} bits"
+                );
+                assert_eq!(ColorDepth::reduce_channel(reduced, bits), reduced);
+            }
+        }
+        assert!((0..=255).all(|value| ColorDepth::reduce_channel(value, 8) == value));
+    }
+
+    #[test]
+    fn depths_represent_their_reduced_colors() {
+        let depth: ColorDepth = "444".parse().unwrap();
+        assert!(depth.represents(0x11, 0x22, 0x33));
+        assert!(!depth.represents(0x12, 0x34, 0x56));
+        let (r, g, b) = depth.reduce_rgb(0x12, 0x34, 0x56);
+        assert!(depth.represents(r, g, b));
+        assert!(ColorDepth::default().represents(0x12, 0x34, 0x56));
+    }
+
+    #[test]
+    fn depths_parse_and_display() {
+        let depth: ColorDepth = "565".parse().unwrap();
+        assert_eq!(
+            depth,
+            ColorDepth {
+                red: 5,
+                green: 6,
+                blue: 5
+            }
+        );
+        assert_eq!(depth.to_string(), "565");
+        assert_eq!("rgb444".parse::<ColorDepth>().unwrap().to_string(), "444");
+        assert!("888".parse::<ColorDepth>().unwrap().is_full());
+        for bad in ["", "44", "4444", "909", "5a5"] {
+            assert!(bad.parse::<ColorDepth>().is_err(), "{bad}");
+        }
+    }
+}
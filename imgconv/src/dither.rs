@@ -0,0 +1,343 @@
+//! Dithering methods
+//!
+//! Error diffusion spreads each pixel's quantization error over the pixels
+//! after it using a kernel. Ordered dithering instead picks between palette
+//! colors with a threshold map that repeats every tile, so flat areas
+//! stay identical tiles and don't shimmer when animated.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Spread of the energy function used to place blue noise thresholds
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// Which dithering to apply when dithering is on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DitherMethod {
+    /// Sierra error diffusion over three rows
+    #[default]
+    Sierra,
+    /// Floyd–Steinberg error diffusion
+    FloydSteinberg,
+    /// Atkinson error diffusion, which only passes on 3/4 of the error
+    Atkinson,
+    /// Sierra Lite error diffusion over two rows
+    SierraLite,
+    /// Ordered dithering with a Bayer matrix
+    Bayer,
+    /// Ordered dithering with a blue noise threshold map
+    BlueNoise,
+}
+
+/// An error diffusion kernel: where the error goes and how much of it
+#[derive(Debug)]
+pub struct DiffusionKernel {
+    /// Sum the weights are divided by
+    pub divisor: f32,
+    /// (x offset, y offset, weight) for each pixel that gets some error
+    pub taps: &'static [(isize, isize, f32)],
+}
+
+const SIERRA: DiffusionKernel = DiffusionKernel {
+    divisor: 32.0,
+    taps: &[
+        (1, 0, 5.0),
+        (2, 0, 3.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 5.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+        (-1, 2, 2.0),
+        (0, 2, 3.0),
+        (1, 2, 2.0),
+    ],
+};
+
+const FLOYD_STEINBERG: DiffusionKernel = DiffusionKernel {
+    divisor: 16.0,
+    taps: &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+};
+
+const ATKINSON: DiffusionKernel = DiffusionKernel {
+    divisor: 8.0,
+    taps: &[
+        (1, 0, 1.0),
+        (2, 0, 1.0),
+        (-1, 1, 1.0),
+        (0, 1, 1.0),
+        (1, 1, 1.0),
+        (0, 2, 1.0),
+    ],
+};
+
+const SIERRA_LITE: DiffusionKernel = DiffusionKernel {
+    divisor: 4.0,
+    taps: &[(1, 0, 2.0), (-1, 1, 1.0), (0, 1, 1.0)],
+};
+
+impl DitherMethod {
+    /// The kernel for error diffusion methods
+    pub fn kernel(self) -> Option<&'static DiffusionKernel> {
+        match self {
+            DitherMethod::Sierra => Some(&SIERRA),
+            DitherMethod::FloydSteinberg => Some(&FLOYD_STEINBERG),
+            DitherMethod::Atkinson => Some(&ATKINSON),
+            DitherMethod::SierraLite => Some(&SIERRA_LITE),
+            DitherMethod::Bayer | DitherMethod::BlueNoise => None,
+        }
+    }
+
+    /// The threshold map for ordered methods, the size of a tile so the
+    /// pattern repeats every tile
+    pub fn threshold_map(self, tile_width: usize, tile_height: usize) -> Option<ThresholdMap> {
+        match self {
+            DitherMethod::Bayer => Some(ThresholdMap::bayer(tile_width, tile_height)),
+            DitherMethod::BlueNoise => Some(ThresholdMap::blue_noise(tile_width, tile_height)),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for DitherMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sierra" => Ok(DitherMethod::Sierra),
+            "floyd-steinberg" => Ok(DitherMethod::FloydSteinberg),
+            "atkinson" => Ok(DitherMethod::Atkinson),
+            "sierra-lite" => Ok(DitherMethod::SierraLite),
+            "bayer" => Ok(DitherMethod::Bayer),
+            "blue-noise" => Ok(DitherMethod::BlueNoise),
+            _ => Err(format!(
+                "{s} is not one of sierra, floyd-steinberg, atkinson, sierra-lite, bayer or blue-noise"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DitherMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DitherMethod::Sierra => "sierra",
+            DitherMethod::FloydSteinberg => "floyd-steinberg",
+            DitherMethod::Atkinson => "atkinson",
+            DitherMethod::SierraLite => "sierra-lite",
+            DitherMethod::Bayer => "bayer",
+            DitherMethod::BlueNoise => "blue-noise",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A rectangle of thresholds between 0 and 1 that tiles the image
+#[derive(Debug, Clone)]
+pub struct ThresholdMap {
+    width: usize,
+    height: usize,
+    thresholds: Vec<f32>,
+}
+
+impl ThresholdMap {
+    /// Build a map from the order each position should turn on in
+    fn from_ranks(width: usize, height: usize, ranks: &[usize]) -> Self {
+        let cells = (width * height) as f32;
+        ThresholdMap {
+            width,
+            height,
+            thresholds: ranks
+                .iter()
+                .map(|&rank| (rank as f32 + 0.5) / cells)
+                .collect(),
+        }
+    }
+
+    /// A Bayer matrix
+    ///
+    /// Sizes other than a square power of two take the corner of the next
+    /// larger square matrix, ranked again so the thresholds stay evenly spread.
+    pub fn bayer(width: usize, height: usize) -> Self {
+        let size = width.max(height).next_power_of_two();
+        let bits = size.trailing_zeros();
+        let square_rank = |x: usize, y: usize| {
+            // Interleave the bits of x ^ y and y, most significant last
+            (0..bits).fold(0, |rank, bit| {
+                let xy = ((x ^ y) >> bit) & 1;
+                let y = (y >> bit) & 1;
+                rank | (xy << (2 * (bits - 1 - bit) + 1)) | (y << (2 * (bits - 1 - bit)))
+            })
+        };
+
+        let mut order: Vec<usize> = (0..width * height).collect();
+        order.sort_by_key(|&i| square_rank(i % width, i / width));
+        let mut ranks = vec![0; width * height];
+        for (rank, i) in order.into_iter().enumerate() {
+            ranks[i] = rank;
+        }
+        Self::from_ranks(width, height, &ranks)
+    }
+
+    /// A blue noise map made with the void-and-cluster method, so nearby
+    /// thresholds are always far apart
+    pub fn blue_noise(width: usize, height: usize) -> Self {
+        let cells = width * height;
+
+        // Energy at every cell from a point, wrapping around the edges
+        let energy_from = |point: usize| -> Vec<f32> {
+            let (px, py) = ((point % width) as isize, (point / width) as isize);
+            (0..cells)
+                .map(|i| {
+                    let wrap = |d: isize, size: usize| {
+                        let d = d.rem_euclid(size as isize);
+                        d.min(size as isize - d) as f32
+                    };
+                    let dx = wrap(((i % width) as isize) - px, width);
+                    let dy = wrap(((i / width) as isize) - py, height);
+                    (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+                })
+                .collect()
+        };
+        let add_energy = |energy: &mut [f32], point: usize, sign: f32| {
+            for (e, delta) in energy.iter_mut().zip(energy_from(point)) {
+                *e += sign * delta;
+            }
+        };
+        // The set cell with the most energy, or the unset cell with the least
+        let tightest_cluster = |set: &[bool], energy: &[f32]| {
+            (0..cells)
+                .filter(|&i| set[i])
+                .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        };
+        let largest_void = |set: &[bool], energy: &[f32]| {
+            (0..cells)
+                .filter(|&i| !set[i])
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        };
+
+        // Start from a spread of a tenth of the cells, then move points from
+        // clusters into voids until that changes nothing
+        let mut set = vec![false; cells];
+        let mut energy = vec![0.0; cells];
+        let initial = (cells / 10).max(1);
+        for i in 0..initial {
+            let point = (i * cells / initial + i * 7) % cells;
+            if !set[point] {
+                set[point] = true;
+                add_energy(&mut energy, point, 1.0);
+            }
+        }
+        for _ in 0..cells * cells {
+            let (Some(cluster), Some(void)) =
+                (tightest_cluster(&set, &energy), largest_void(&set, &energy))
+            else {
+                break;
+            };
+            set[cluster] = false;
+            add_energy(&mut energy, cluster, -1.0);
+            let void = largest_void(&set, &energy).unwrap_or(void);
+            if void == cluster {
+                set[cluster] = true;
+                add_energy(&mut energy, cluster, 1.0);
+                break;
+            }
+            set[void] = true;
+            add_energy(&mut energy, void, 1.0);
+        }
+
+        let mut ranks = vec![0; cells];
+        let points = set.iter().filter(|&&set| set).count();
+
+        // Rank the initial points by taking them out from the tightest cluster
+        let (mut removing, mut removing_energy) = (set.clone(), energy.clone());
+        for rank in (0..points).rev() {
+            if let Some(cluster) = tightest_cluster(&removing, &removing_energy) {
+                removing[cluster] = false;
+                add_energy(&mut removing_energy, cluster, -1.0);
+                ranks[cluster] = rank;
+            }
+        }
+
+        // Rank the rest by filling in the largest void
+        for rank in points..cells {
+            if let Some(void) = largest_void(&set, &energy) {
+                set[void] = true;
+                add_energy(&mut energy, void, 1.0);
+                ranks[void] = rank;
+            }
+        }
+
+        Self::from_ranks(width, height, &ranks)
+    }
+
+    /// The threshold for a pixel, between 0 and 1
+    pub fn threshold(&self, x: usize, y: usize) -> f32 {
+        self.thresholds[(y % self.height) * self.width + x % self.width]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rank of every cell of a map, row by row
+    fn ranks(map: &ThresholdMap) -> Vec<usize> {
+        let cells = (map.width * map.height) as f32;
+        (0..map.height)
+            .flat_map(|y| (0..map.width).map(move |x| (x, y)))
+            .map(|(x, y)| (map.threshold(x, y) * cells - 0.5).round() as usize)
+            .collect()
+    }
+
+    /// Whether every rank from 0 up appears once
+    fn is_permutation(mut ranks: Vec<usize>) -> bool {
+        ranks.sort_unstable();
+        ranks.iter().copied().eq(0..ranks.len())
+    }
+
+    #[test]
+    fn square_bayer_maps_are_the_standard_matrix() {
+        assert_eq!(
+            ranks(&ThresholdMap::bayer(4, 4)),
+            [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5]
+        );
+    }
+
+    #[test]
+    fn maps_use_every_threshold_once_whatever_the_tile_size() {
+        for (width, height) in [(8, 8), (16, 16), (8, 16), (6, 10), (1, 1)] {
+            for method in [DitherMethod::Bayer, DitherMethod::BlueNoise] {
+                let map = method.threshold_map(width, height).unwrap();
+                assert_eq!((map.width, map.height), (width, height));
+                assert!(is_permutation(ranks(&map)), "{method} {width}x{height}");
+            }
+        }
+        assert!(DitherMethod::Sierra.threshold_map(8, 8).is_none());
+    }
+
+    #[test]
+    fn maps_repeat_in_each_direction() {
+        let map = ThresholdMap::bayer(8, 16);
+        assert_eq!(map.threshold(3, 5), map.threshold(3 + 8, 5 + 16));
+        assert_eq!(map.threshold(7, 15), map.threshold(15, 31));
+        assert_ne!(map.threshold(3, 5), map.threshold(3, 5 + 8));
+    }
+
+    #[test]
+    fn methods_parse_as_displayed() {
+        for method in [
+            DitherMethod::Sierra,
+            DitherMethod::FloydSteinberg,
+            DitherMethod::Atkinson,
+            DitherMethod::SierraLite,
+            DitherMethod::Bayer,
+            DitherMethod::BlueNoise,
+        ] {
+            assert_eq!(method.to_string().parse::<DitherMethod>(), Ok(method));
+        }
+        assert!("ordered".parse::<DitherMethod>().is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! Fitting arbitrary images to a whole number of tiles
+//!
+//! The converter works on an image that is exactly the tilemap size. This
+//! derives the tilemap size from the image, or pads, crops or scales the
+//! image to the configured tilemap size.
+
+use std::fmt;
+use std::str::FromStr;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::imgconv::{Config, ConversionError};
+
+/// How to make the image fit the tilemap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FitMode {
+    /// The image must already be exactly the tilemap size
+    Exact,
+    /// Size the tilemap to the image, padding up to a whole number of tiles
+    #[default]
+    Derive,
+    /// Place the image on the tilemap unscaled, padding or cropping it
+    Pad,
+    /// Scale the image to fit inside the tilemap keeping its aspect ratio,
+    /// then pad the rest
+    Scale,
+}
+
+/// Where the image is placed when it is padded or cropped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    #[default]
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Offset of the image on the canvas, negative where it is cropped
+    fn offset(self, canvas: (u32, u32), image: (u32, u32)) -> (i64, i64) {
+        let spare_x = canvas.0 as i64 - image.0 as i64;
+        let spare_y = canvas.1 as i64 - image.1 as i64;
+
+        let x = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => spare_x / 2,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => spare_x,
+        };
+        let y = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0,
+            Anchor::Left | Anchor::Center | Anchor::Right => spare_y / 2,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => spare_y,
+        };
+
+        (x, y)
+    }
+}
+
+impl FromStr for FitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(FitMode::Exact),
+            "derive" => Ok(FitMode::Derive),
+            "pad" => Ok(FitMode::Pad),
+            "scale" => Ok(FitMode::Scale),
+            _ => Err(format!("{s} is not one of exact, derive, pad or scale")),
+        }
+    }
+}
+
+impl fmt::Display for FitMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FitMode::Exact => "exact",
+            FitMode::Derive => "derive",
+            FitMode::Pad => "pad",
+            FitMode::Scale => "scale",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Anchor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Anchor::TopLeft),
+            "top" => Ok(Anchor::Top),
+            "top-right" => Ok(Anchor::TopRight),
+            "left" => Ok(Anchor::Left),
+            "center" => Ok(Anchor::Center),
+            "right" => Ok(Anchor::Right),
+            "bottom-left" => Ok(Anchor::BottomLeft),
+            "bottom" => Ok(Anchor::Bottom),
+            "bottom-right" => Ok(Anchor::BottomRight),
+            _ => Err(format!(
+                "{s} is not one of top-left, top, top-right, left, center, right, \
+                 bottom-left, bottom or bottom-right"
+            )),
+        }
+    }
+}
+
+/// Parse a `rrggbb` colour, with or without a leading `#`
+pub fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let digits = color.strip_prefix('#').unwrap_or(color);
+    if digits.len() != 6 {
+        return Err(format!("{color} is not a rrggbb colour"));
+    }
+    let value =
+        u32::from_str_radix(digits, 16).map_err(|e| format!("{color} is not a colour: {e}"))?;
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+/// Fit the image to the tilemap as configured, returning the fitted image
+/// and the configuration with the tilemap size it ended up with
+pub fn fit_image(
+    img: &DynamicImage,
+    config: &Config,
+) -> Result<(DynamicImage, Config), ConversionError> {
+    let mut config = config.clone();
+    let (width, height) = img.dimensions();
+
+    match config.fit {
+        FitMode::Exact => {
+            // Image must have width and height that are multiples of the tile size
+            if width % config.tile_width != 0 || height % config.tile_height != 0 {
+                return Err(ConversionError::InvalidDimensions(
+                    width,
+                    height,
+                    config.tile_width,
+                    config.tile_height,
+                ));
+            }
+
+            // Verify tilemap dimensions match image dimensions
+            if width != config.total_width() || height != config.total_height() {
+                return Err(ConversionError::DimensionMismatch(
+                    width,
+                    height,
+                    config.total_width(),
+                    config.total_height(),
+                ));
+            }
+
+            return Ok((img.clone(), config));
+        }
+        FitMode::Derive => {
+            config.tilemap_width = width.div_ceil(config.tile_width);
+            config.tilemap_height = height.div_ceil(config.tile_height);
+        }
+        FitMode::Pad | FitMode::Scale => {}
+    }
+
+    let canvas_size = (config.total_width(), config.total_height());
+    if (width, height) == canvas_size {
+        return Ok((img.clone(), config));
+    }
+
+    let image = match config.fit {
+        FitMode::Scale => {
+            let scale = f64::min(
+                canvas_size.0 as f64 / width as f64,
+                canvas_size.1 as f64 / height as f64,
+            );
+            let scaled_width = ((width as f64 * scale).round() as u32).clamp(1, canvas_size.0);
+            let scaled_height = ((height as f64 * scale).round() as u32).clamp(1, canvas_size.1);
+            imageops::resize(img, scaled_width, scaled_height, FilterType::Lanczos3)
+        }
+        _ => img.to_rgba8(),
+    };
+
+    // Padding is transparent when the conversion has transparency
+    let [r, g, b] = parse_color(&config.fill_color).map_err(ConversionError::InvalidConfig)?;
+    let alpha = if config.transparency { 0 } else { 255 };
+    let mut canvas = RgbaImage::from_pixel(canvas_size.0, canvas_size.1, Rgba([r, g, b, alpha]));
+    let (x, y) = config.anchor.offset(canvas_size, image.dimensions());
+    imageops::overlay(&mut canvas, &image, x, y);
+
+    Ok((DynamicImage::ImageRgba8(canvas), config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            width,
+            height,
+            Rgba([10, 20, 30, 255]),
+        ))
+    }
+
+    fn config(fit: FitMode) -> Config {
+        Config {
+            fit,
+            tilemap_width: 4,
+            tilemap_height: 2,
+            fill_color: "ff0000".to_string(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn derive_pads_up_to_whole_tiles() {
+        let (fitted, config) = fit_image(&image(20, 9), &config(FitMode::Derive)).unwrap();
+        assert_eq!((config.tilemap_width, config.tilemap_height), (3, 2));
+        assert_eq!(fitted.dimensions(), (24, 16));
+        assert_eq!(fitted.get_pixel(19, 8), Rgba([10, 20, 30, 255]));
+        assert_eq!(fitted.get_pixel(20, 8), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn exact_requires_the_tilemap_size() {
+        assert!(fit_image(&image(32, 16), &config(FitMode::Exact)).is_ok());
+        assert!(matches!(
+            fit_image(&image(30, 16), &config(FitMode::Exact)),
+            Err(ConversionError::InvalidDimensions(30, 16, 8, 8))
+        ));
+        assert!(matches!(
+            fit_image(&image(40, 16), &config(FitMode::Exact)),
+            Err(ConversionError::DimensionMismatch(40, 16, 32, 16))
+        ));
+    }
+
+    #[test]
+    fn pad_places_the_image_at_the_anchor_and_crops_it() {
+        let config = Config {
+            anchor: Anchor::BottomRight,
+            ..config(FitMode::Pad)
+        };
+        let (fitted, _) = fit_image(&image(10, 40), &config).unwrap();
+        assert_eq!(fitted.dimensions(), (32, 16));
+        assert_eq!(fitted.get_pixel(21, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(fitted.get_pixel(22, 0), Rgba([10, 20, 30, 255]));
+        assert_eq!(fitted.get_pixel(31, 15), Rgba([10, 20, 30, 255]));
+    }
+
+    #[test]
+    fn scale_keeps_the_aspect_ratio() {
+        let config = Config {
+            anchor: Anchor::Center,
+            ..config(FitMode::Scale)
+        };
+        // Scaled to 16x16, centred with 8 pixels of padding either side
+        let (fitted, _) = fit_image(&image(8, 8), &config).unwrap();
+        assert_eq!(fitted.dimensions(), (32, 16));
+        assert_eq!(fitted.get_pixel(7, 8), Rgba([255, 0, 0, 255]));
+        assert_eq!(fitted.get_pixel(8, 8), Rgba([10, 20, 30, 255]));
+        assert_eq!(fitted.get_pixel(23, 8), Rgba([10, 20, 30, 255]));
+        assert_eq!(fitted.get_pixel(24, 8), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn padding_is_transparent_with_transparency() {
+        let config = Config {
+            transparency: true,
+            ..config(FitMode::Pad)
+        };
+        let (fitted, _) = fit_image(&image(8, 8), &config).unwrap();
+        assert_eq!(fitted.get_pixel(8, 0)[3], 0);
+    }
+
+    #[test]
+    fn colors_parse_with_or_without_a_hash() {
+        assert_eq!(parse_color("#12ab34"), Ok([0x12, 0xab, 0x34]));
+        assert_eq!(parse_color("000000"), Ok([0, 0, 0]));
+        assert!(parse_color("fff").is_err());
+        assert!(parse_color("12345g").is_err());
+    }
+}
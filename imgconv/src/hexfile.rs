@@ -0,0 +1,210 @@
+//! Reader for the `$readmemh` hex files the converter writes
+//!
+//! Words are whitespace separated hex numbers loaded at consecutive addresses,
+//! `@addr` moves the load address and `//` starts a comment, the same as
+//! Verilog's `$readmemh`. The emulator loads its hex files with this too.
+
+use std::io;
+use std::path::Path;
+
+use crate::imgconv::ConversionError;
+
+/// Number of words in the VDP's VRAM, the largest memory a hex file loads
+pub const MAX_WORDS: usize = 4 * 1024 * 1024;
+
+/// A word of a hex file, with the address it loads into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexWord<'a> {
+    /// Line of the file the word is on, from 1
+    pub line: usize,
+    /// Address the word loads into
+    pub address: usize,
+    /// The hex digits of the word
+    pub digits: &'a str,
+}
+
+impl HexWord<'_> {
+    /// Parse the word as a number of at most 64 bits
+    pub fn value(&self) -> Result<u64, String> {
+        u64::from_str_radix(self.digits, 16).map_err(|e| self.error(e))
+    }
+
+    /// Parse the word as a record of `count` 36 bit fields, like the sprite
+    /// BRAM. Field 0 is the least significant 36 bits, `bram[i][35:0]` in the RTL.
+    pub fn fields_36(&self, count: usize) -> Result<Vec<u64>, String> {
+        if self.digits.len() > count * 9 {
+            return Err(self.error(format!("wider than {} bits", count * 36)));
+        }
+
+        let mut fields = Vec::with_capacity(count);
+        let mut end = self.digits.len();
+        for _ in 0..count {
+            let start = end.saturating_sub(9);
+            let field = &self.digits[start..end];
+            fields.push(if field.is_empty() {
+                0
+            } else {
+                u64::from_str_radix(field, 16).map_err(|e| self.error(e))?
+            });
+            end = start;
+        }
+        Ok(fields)
+    }
+
+    /// An error about this word
+    pub fn error(&self, error: impl std::fmt::Display) -> String {
+        format!("line {}: bad word {}: {error}", self.line, self.digits)
+    }
+}
+
+/// Read the text of a hex file
+pub fn read_text(path: impl AsRef<Path>) -> io::Result<String> {
+    let path = path.as_ref();
+    std::fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+}
+
+/// Read a hex file into memory, with unwritten addresses left as zero
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<u32>, ConversionError> {
+    let path = path.as_ref();
+    let text = read_text(path)?;
+    parse(&text).map_err(|e| ConversionError::InvalidHex(format!("{}: {e}", path.display())))
+}
+
+/// Parse the text of a hex file
+pub fn parse(text: &str) -> Result<Vec<u32>, String> {
+    let mut memory = Vec::new();
+
+    for word in words(text)? {
+        let value = u32::from_str_radix(word.digits, 16).map_err(|e| word.error(e))?;
+        if memory.len() <= word.address {
+            memory.resize(word.address + 1, 0);
+        }
+        memory[word.address] = value;
+    }
+
+    Ok(memory)
+}
+
+/// Split the text of a hex file into words with their load addresses
+pub fn words(text: &str) -> Result<Vec<HexWord<'_>>, String> {
+    let mut words = Vec::new();
+    let mut address = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or("");
+        for token in line.split_whitespace() {
+            if let Some(target) = token.strip_prefix('@') {
+                address = usize::from_str_radix(target, 16)
+                    .map_err(|e| format!("line {}: bad address {token}: {e}", index + 1))?;
+                continue;
+            }
+
+            if address >= MAX_WORDS {
+                return Err(format!(
+                    "line {}: address {address:x} is past the {MAX_WORDS} words of VRAM",
+                    index + 1
+                ));
+            }
+            words.push(HexWord {
+                line: index + 1,
+                address,
+                digits: token,
+            });
+            address += 1;
+        }
+    }
+
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_load_at_consecutive_addresses() {
+        let text = "// palette 0\n00ff00 123456\n@10 abcd // moved\nffff\n";
+        assert_eq!(
+            words(text).unwrap(),
+            [
+                HexWord {
+                    line: 2,
+                    address: 0,
+                    digits: "00ff00"
+                },
+                HexWord {
+                    line: 2,
+                    address: 1,
+                    digits: "123456"
+                },
+                HexWord {
+                    line: 3,
+                    address: 0x10,
+                    digits: "abcd"
+                },
+                HexWord {
+                    line: 4,
+                    address: 0x11,
+                    digits: "ffff"
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unwritten_addresses_are_zero() {
+        assert_eq!(parse("1 @3 2\n@1 4").unwrap(), [1, 4, 0, 2]);
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_words_and_addresses_name_their_line() {
+        assert_eq!(
+            parse("1\n12xz").unwrap_err(),
+            "line 2: bad word 12xz: invalid digit found in string"
+        );
+        assert!(parse("@zz 1")
+            .unwrap_err()
+            .starts_with("line 1: bad address @zz"));
+        assert!(parse("123456789").is_err());
+    }
+
+    #[test]
+    fn addresses_past_vram_are_rejected() {
+        let last = format!("@{:x} 1", MAX_WORDS - 1);
+        assert_eq!(words(&last).unwrap()[0].address, MAX_WORDS - 1);
+
+        let past = format!("@{MAX_WORDS:x} 1");
+        assert_eq!(
+            words(&past).unwrap_err(),
+            format!("line 1: address 400000 is past the {MAX_WORDS} words of VRAM")
+        );
+        // an address directive alone writes nothing
+        assert!(words(&format!("@{MAX_WORDS:x}")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn records_split_into_36_bit_fields() {
+        let word = HexWord {
+            line: 1,
+            address: 0,
+            digits: "000000004000000003000000002000000001",
+        };
+        assert_eq!(word.fields_36(4).unwrap(), [1, 2, 3, 4]);
+
+        // shorter records leave the top fields zero
+        let word = HexWord {
+            digits: "a00000001",
+            ..word
+        };
+        assert_eq!(word.fields_36(4).unwrap(), [0xa_0000_0001, 0, 0, 0]);
+        assert_eq!(word.value().unwrap(), 0xa_0000_0001);
+
+        let word = HexWord {
+            digits: "1000000000",
+            ..word
+        };
+        assert!(word.fields_36(1).is_err());
+    }
+}
@@ -3,35 +3,57 @@
 //! This module handles the conversion of images to tiles, palettes, and tilemaps
 //! for use in graphics hardware or software.
 
-use std::fs::File;
-use std::io::{self, Write};
-
-use image::{GenericImageView, Pixel, RgbImage};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImageView, GrayImage, Pixel};
 use kmeans::{KMeans, KMeansConfig};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::color::{find_similar_color, oklab_delta_e, ColorFrequency, Oklab, OklabDistance};
+use crate::color::{
+    find_similar_color, oklab_delta_e, ColorDepth, ColorFrequency, Oklab, OklabDistance,
+};
+use crate::dither::{DiffusionKernel, DitherMethod, ThresholdMap};
+use crate::fit::{self, Anchor, FitMode};
+use crate::hexfile;
+use crate::output::MaskGranularity;
 
 // Constants to replace magic numbers
 /// Number of bits per color index in output tile data
-const BITS_PER_COLOR: usize = 4;
+pub const BITS_PER_COLOR: usize = 4;
 /// Number of pixels per u16 chunk in output tile data (16 bits / 4 bits per pixel)
-const PIXELS_PER_CHUNK: usize = 16 / BITS_PER_COLOR;
-/// Divisor used for dithering error calculation
-const DITHER_ERROR_DIVISOR: f32 = 32.0;
+pub const PIXELS_PER_CHUNK: usize = 16 / BITS_PER_COLOR;
+/// Number of candidate colors mixed for each pixel by ordered dithering
+const ORDERED_DITHER_CANDIDATES: usize = 16;
 /// Maximum number of k-means iterations for palette generation
 const KMEANS_MAX_ITERATIONS: usize = 10000;
 /// Maximum number of k-means iterations for color reduction
 const COLOR_REDUCTION_MAX_ITERATIONS: usize = 100000;
-/// Number of u16 chunks per row in output file
-const CHUNKS_PER_ROW: usize = 2;
+/// Maximum number of further iterations with the centres snapped to the color depth
+const COLOR_DEPTH_MAX_ITERATIONS: usize = 100;
+/// Number of palettes a tilemap entry can select (5 bit palette index)
+pub const MAX_PALETTES: usize = 32;
+/// Number of colors a 4 bit texel can select
+pub const MAX_COLORS_PER_PALETTE: usize = 1 << BITS_PER_COLOR;
+/// Number of tiles a tilemap entry can select (10 bit tile index)
+pub const MAX_UNIQUE_TILES: usize = 1024;
 /// Bit position for palette index in tilemap entry
-const PALETTE_INDEX_SHIFT: usize = 10;
-/// Delta E multiplier for error metrics display
-const DELTA_E_DISPLAY_FACTOR: f32 = 100.0;
-/// Maximum pixel value for PSNR calculation (8-bit color)
-const MAX_PIXEL_VALUE: f32 = 255.0;
+pub const PALETTE_INDEX_SHIFT: usize = 10;
+/// Mask for the palette index in a tilemap entry, after shifting
+pub const PALETTE_INDEX_MASK: u32 = 0x1f;
+/// Tilemap entry bit mirroring the tile horizontally
+pub const X_FLIP_BIT: u16 = 1 << 15;
+/// Tilemap entry bit mirroring the tile vertically, taking the top palette index bit
+pub const Y_FLIP_BIT: u16 = 1 << 14;
+/// Color index the line buffer is cleared to, reserved for transparent pixels
+pub const TRANSPARENT_INDEX: usize = 0;
+/// Lightness standing in for transparent pixels when clustering tiles, away from any color
+const TRANSPARENT_LIGHTNESS: f32 = -1.0;
 
 /// Errors that can occur during image conversion
 #[derive(Error, Debug)]
@@ -51,12 +73,27 @@ pub enum ConversionError {
     #[error("JSON error: {0}")]
     JsonError(#[from] serde_json::Error),
 
+    #[error("TOML error: {0}")]
+    TomlError(#[from] toml::de::Error),
+
     #[error("Error generating palettes: {0}")]
     PaletteGeneration(String),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Invalid hex file: {0}")]
+    InvalidHex(String),
+
+    #[error("Invalid binary asset file: {0}")]
+    InvalidBinary(String),
 }
 
 /// Configuration for the image conversion process
+///
+/// When loaded from a file, missing fields take their default values.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     /// Input image file path
     pub input_file: String,
@@ -70,6 +107,23 @@ pub struct Config {
     pub output_tilemap_hex: String,
     /// Output JSON file path (optional)
     pub output_json: Option<String>,
+    /// Output binary asset file path (optional), with a header then every
+    /// section, see the `binfile` module
+    pub output_bin: Option<String>,
+    /// Directory to write headerless `palette.bin`, `tiles.bin`,
+    /// `tile_map.bin` and, with transparency, `mask.bin` to (optional)
+    pub output_raw_dir: Option<String>,
+    /// Output Rust module path (optional), with the assets as const arrays
+    pub output_rust: Option<String>,
+    /// Output C header path (optional), with the assets as const arrays
+    pub output_c_header: Option<String>,
+    /// Output JSON quality report path (optional), see
+    /// [`crate::metrics::QualityReport`]
+    pub output_report: Option<String>,
+    /// Output transparency mask hex file path (optional)
+    pub output_mask_hex: Option<String>,
+    /// Whether the mask has a bit per texel or per doubled pixel
+    pub mask_granularity: MaskGranularity,
     /// Tile width in pixels
     pub tile_width: u32,
     /// Tile height in pixels
@@ -78,6 +132,13 @@ pub struct Config {
     pub tilemap_width: u32,
     /// Height of the tilemap in tiles
     pub tilemap_height: u32,
+    /// How to fit the image to the tilemap. The default derives the tilemap
+    /// size from the image, ignoring `tilemap_width` and `tilemap_height`
+    pub fit: FitMode,
+    /// Where the image goes when it is padded or cropped
+    pub anchor: Anchor,
+    /// `rrggbb` colour for padding
+    pub fill_color: String,
     /// Number of palettes to generate
     pub num_palettes: usize,
     /// Maximum colors per palette
@@ -86,10 +147,32 @@ pub struct Config {
     pub dithering: bool,
     /// Error scaling factor for dithering
     pub dither_factor: f32,
+    /// Which dithering to apply
+    pub dither_method: DitherMethod,
+    /// Grayscale image the size of the input, not dithered where it's black
+    /// (optional, read by the command line tool)
+    pub dither_mask: Option<String>,
+    /// Passes that dither again in the palettes the tiles finally got, then
+    /// pick the unique tiles again, until the palettes stop changing. The
+    /// last pass keeps its palettes, so tiles are shown in the palette they
+    /// were dithered in. 0 leaves the first dither as it is.
+    pub redither_passes: usize,
     /// Threshold for color similarity
     pub color_similarity_threshold: f32,
+    /// Bits per channel of the palette RAM, like `565`. Palette colors are
+    /// reduced to it before quantizing, so the preview matches the hardware.
+    pub color_depth: ColorDepth,
     /// Maximum number of unique tiles (default 256, max 1024)
     pub max_unique_tiles: usize,
+    /// Which mirrored copies of a tile can share it, flipped by the tilemap entry
+    pub tile_flips: TileFlips,
+    /// Palettes that are used as they are, taking the first palette indices.
+    /// Only the rest of the palettes are generated.
+    pub locked_palettes: Vec<Palette>,
+    /// Reserve color 0 of every palette for transparent pixels
+    pub transparency: bool,
+    /// Pixels with alpha below this are transparent, when transparency is on
+    pub alpha_threshold: u8,
 }
 
 impl Default for Config {
@@ -101,21 +184,171 @@ impl Default for Config {
             output_tiles_hex: "rtl/tiles.hex".to_string(),
             output_tilemap_hex: "rtl/tile_map.hex".to_string(),
             output_json: None,
+            output_bin: None,
+            output_raw_dir: None,
+            output_rust: None,
+            output_c_header: None,
+            output_report: None,
+            output_mask_hex: None,
+            mask_granularity: MaskGranularity::default(),
             tile_width: 8,
             tile_height: 8,
             tilemap_width: 32,
             tilemap_height: 32,
+            fit: FitMode::default(),
+            anchor: Anchor::default(),
+            fill_color: "000000".to_string(),
             num_palettes: 32,
             colors_per_palette: 16,
             dithering: true,
             dither_factor: 0.75,
+            dither_method: DitherMethod::default(),
+            dither_mask: None,
+            redither_passes: 2,
             color_similarity_threshold: 0.005,
+            color_depth: ColorDepth::default(),
             max_unique_tiles: 256,
+            tile_flips: TileFlips::default(),
+            locked_palettes: Vec::new(),
+            transparency: false,
+            alpha_threshold: 128,
         }
     }
 }
 
 impl Config {
+    /// Load a configuration from a JSON or TOML file, chosen by its extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConversionError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(serde_json::from_str(&text)?),
+            Some("toml") => Ok(toml::from_str(&text)?),
+            _ => Err(ConversionError::InvalidConfig(format!(
+                "{} is not a .json or .toml file",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Check the settings are ones the converter and the hardware support
+    pub fn validate(&self) -> Result<(), ConversionError> {
+        let invalid = |message: String| Err(ConversionError::InvalidConfig(message));
+
+        if self.tile_width == 0 || self.tile_height == 0 {
+            return invalid(format!(
+                "tile size {}x{} must not be zero",
+                self.tile_width, self.tile_height
+            ));
+        }
+        if self.tile_width as usize % PIXELS_PER_CHUNK != 0 {
+            return invalid(format!(
+                "tile width {} must be a multiple of {PIXELS_PER_CHUNK} pixels, \
+                 so each tile row is whole texel words",
+                self.tile_width
+            ));
+        }
+        if self.tilemap_width == 0 || self.tilemap_height == 0 {
+            return invalid(format!(
+                "tilemap size {}x{} must not be zero",
+                self.tilemap_width, self.tilemap_height
+            ));
+        }
+        if !(1..=MAX_PALETTES).contains(&self.num_palettes) {
+            return invalid(format!(
+                "{} palettes is not between 1 and {MAX_PALETTES}",
+                self.num_palettes
+            ));
+        }
+        if self.num_palettes > self.tile_flips.max_palettes() {
+            return invalid(format!(
+                "{} palettes don't leave a tilemap entry bit for vertical flips, \
+                 use at most {}",
+                self.num_palettes,
+                self.tile_flips.max_palettes()
+            ));
+        }
+        if self.num_palettes > self.total_tiles() {
+            return invalid(format!(
+                "{} palettes is more than the {} tiles in the tilemap",
+                self.num_palettes,
+                self.total_tiles()
+            ));
+        }
+        if !(1..=MAX_COLORS_PER_PALETTE).contains(&self.colors_per_palette) {
+            return invalid(format!(
+                "{} colors per palette is not between 1 and {MAX_COLORS_PER_PALETTE}",
+                self.colors_per_palette
+            ));
+        }
+        if self.transparency && self.colors_per_palette < 2 {
+            return invalid(format!(
+                "{} colors per palette leaves none besides the transparent color",
+                self.colors_per_palette
+            ));
+        }
+        if self.locked_palettes.len() > self.num_palettes {
+            return invalid(format!(
+                "{} locked palettes is more than {} palettes",
+                self.locked_palettes.len(),
+                self.num_palettes
+            ));
+        }
+        for (i, palette) in self.locked_palettes.iter().enumerate() {
+            if palette.colors.len() > self.colors_per_palette {
+                return invalid(format!(
+                    "locked palette {i} has {} colors, more than {}",
+                    palette.colors.len(),
+                    self.colors_per_palette
+                ));
+            }
+            if palette.transparent != self.transparency {
+                return invalid(format!(
+                    "locked palette {i} {} a transparent color but transparency is {}",
+                    if palette.transparent {
+                        "has"
+                    } else {
+                        "doesn't have"
+                    },
+                    if self.transparency { "on" } else { "off" }
+                ));
+            }
+            // Locked colors are used unchanged, so they must already be ones
+            // the palette RAM can hold
+            let unrepresentable = palette
+                .colors
+                .iter()
+                .enumerate()
+                .filter(|&(index, _)| !palette.is_transparent(index))
+                .map(|(_, color)| color.color.to_rgb())
+                .find(|&(r, g, b)| !self.color_depth.represents(r, g, b));
+            if let Some((r, g, b)) = unrepresentable {
+                return invalid(format!(
+                    "locked palette {i} has color {r:02x}{g:02x}{b:02x}, which RGB{} can't show",
+                    self.color_depth
+                ));
+            }
+        }
+        if !(1..=MAX_UNIQUE_TILES).contains(&self.max_unique_tiles) {
+            return invalid(format!(
+                "{} unique tiles is not between 1 and {MAX_UNIQUE_TILES}",
+                self.max_unique_tiles
+            ));
+        }
+        if !self.dither_factor.is_finite() || self.dither_factor < 0.0 {
+            return invalid(format!(
+                "dither factor {} must be a positive number",
+                self.dither_factor
+            ));
+        }
+        if let Err(message) = fit::parse_color(&self.fill_color) {
+            return invalid(format!("fill color {message}"));
+        }
+
+        Ok(())
+    }
+
     /// Get total number of tiles in the tilemap
     pub fn total_tiles(&self) -> usize {
         (self.tilemap_width * self.tilemap_height) as usize
@@ -140,6 +373,16 @@ impl Config {
     pub fn chunks_per_tile(&self) -> usize {
         self.tile_size().div_ceil(PIXELS_PER_CHUNK)
     }
+
+    /// Get the number of chunks in one row of a tile, 2 for 8 pixel wide tiles
+    pub fn chunks_per_row(&self) -> usize {
+        self.tile_width as usize / PIXELS_PER_CHUNK
+    }
+
+    /// Get the number of colors per palette available to opaque pixels
+    pub fn opaque_colors_per_palette(&self) -> usize {
+        self.colors_per_palette - self.transparency as usize
+    }
 }
 
 /// Represents an entire tilemap with all its data
@@ -149,6 +392,30 @@ pub struct TilemapData {
     pub tiles: Vec<Tile>,
     pub palettes: Vec<Palette>,
     pub tilemap: Vec<TilemapEntry>,
+    /// The tiles the tilemap entries index, as written to the tiles hex file
+    pub unique_tiles: Vec<UniqueTile>,
+    /// Counts from the stages of the conversion
+    #[serde(default)]
+    pub stats: ConversionStats,
+}
+
+/// Counts from the stages of a conversion, for the caller to report
+///
+/// A batch conversion shares them between all its tilemaps.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConversionStats {
+    /// Fewest colors a palette was generated from, before reducing them
+    pub min_palette_colors: usize,
+    /// Most colors a palette was generated from, before reducing them
+    pub max_palette_colors: usize,
+    /// Number of tiles in all the images
+    pub tiles: usize,
+    /// Tiles left after merging exact copies and flippable mirror images
+    pub distinct_tiles: usize,
+    /// Number of unique tiles the distinct tiles were clustered into
+    pub unique_tiles: usize,
+    /// Tiles that changed palette before each re-dithering pass
+    pub redither_changes: Vec<usize>,
 }
 
 /// Represents a single tile
@@ -156,21 +423,92 @@ pub struct TilemapData {
 pub struct Tile {
     pub pixels: Vec<Oklab>,
     pub quantized: Vec<u16>,
+    /// The palette the tile was quantized and dithered in
+    #[serde(default)]
+    pub palette: usize,
 }
 
 /// Represents a palette of colors
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Palette {
     pub colors: Vec<ColorFrequency>,
+    /// Whether color 0 is reserved for transparent pixels
+    #[serde(default)]
+    pub transparent: bool,
 }
 
 impl Palette {
+    /// Read palettes from a file: a palette hex file with one palette per line,
+    /// or a JSON array of palettes. Palettes from a hex file reserve their
+    /// first color for transparency if `transparent` is set.
+    pub fn load(
+        path: impl AsRef<Path>,
+        transparent: bool,
+    ) -> Result<Vec<Palette>, ConversionError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+            return Ok(serde_json::from_str(&text)?);
+        }
+
+        let mut palettes = Vec::new();
+        for line in text.lines() {
+            let words = hexfile::parse(line)
+                .map_err(|e| ConversionError::InvalidHex(format!("{}: {e}", path.display())))?;
+            if words.is_empty() {
+                continue;
+            }
+
+            let colors = words
+                .iter()
+                .map(|&rgb| {
+                    let color = Oklab::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8);
+                    ColorFrequency::new(color, 0)
+                })
+                .collect();
+            palettes.push(Palette {
+                colors,
+                transparent,
+            });
+        }
+
+        Ok(palettes)
+    }
+
+    /// Whether every opaque pixel of a tile is one of this palette's colors
+    fn matches_tile(&self, tile: &[Oklab], opaque: &[bool], threshold: f32) -> bool {
+        tile.iter()
+            .zip(opaque)
+            .filter(|(_, &opaque)| opaque)
+            .all(|(&color, _)| {
+                self.opaque_colors()
+                    .iter()
+                    .any(|palette_color| oklab_delta_e(color, palette_color.color) < threshold)
+            })
+    }
+
+    /// Index of the first color opaque pixels can use
+    fn first_opaque(&self) -> usize {
+        self.transparent as usize
+    }
+
+    /// The colors opaque pixels can use
+    pub fn opaque_colors(&self) -> &[ColorFrequency] {
+        self.colors.get(self.first_opaque()..).unwrap_or_default()
+    }
+
+    /// Whether the color index is the reserved transparent color
+    pub fn is_transparent(&self, index: usize) -> bool {
+        self.transparent && index == TRANSPARENT_INDEX
+    }
+
     /// Find the best matching color index for the given color
     pub fn find_best_color(&self, color: Oklab) -> usize {
         let mut min_delta_e = f32::MAX;
-        let mut min_index = 0;
+        let mut min_index = self.first_opaque();
 
-        for (i, palette_color) in self.colors.iter().enumerate() {
+        for (i, palette_color) in self.colors.iter().enumerate().skip(self.first_opaque()) {
             let delta_e = oklab_delta_e(color, palette_color.color);
             if delta_e < min_delta_e {
                 min_delta_e = delta_e;
@@ -181,19 +519,79 @@ impl Palette {
         min_index
     }
 
-    /// Calculate the average luminance of colors in this palette
+    /// Calculate the average luminance of the opaque colors in this palette
     pub fn average_luminance(&self) -> f32 {
-        if self.colors.is_empty() {
+        let colors = self.opaque_colors();
+        if colors.is_empty() {
             return 0.0;
         }
 
-        self.colors.iter().map(|c| c.color.l).sum::<f32>() / self.colors.len() as f32
+        colors.iter().map(|c| c.color.l).sum::<f32>() / colors.len() as f32
     }
 
-    /// Sort colors by luminance
+    /// Sort the opaque colors by luminance
     pub fn sort_by_luminance(&mut self) {
-        self.colors
-            .sort_by(|a, b| a.color.l.partial_cmp(&b.color.l).unwrap());
+        let first = self.first_opaque().min(self.colors.len());
+        self.colors[first..].sort_by(|a, b| a.color.l.partial_cmp(&b.color.l).unwrap());
+    }
+}
+
+/// Which tile flips the tilemap entries encode
+///
+/// Bit 15 of a tilemap entry is spare, so it holds the horizontal flip.
+/// A vertical flip needs bit 14 as well, leaving 4 bits for the palette.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TileFlips {
+    /// Mirrored tiles are distinct, for RTL without per-tile flip
+    #[default]
+    Off,
+    /// Horizontally mirrored tiles share a tile
+    Horizontal,
+    /// Horizontally and vertically mirrored tiles share a tile
+    Both,
+}
+
+impl TileFlips {
+    /// The (x_flip, y_flip) combinations a tilemap entry can use
+    pub fn variants(self) -> &'static [(bool, bool)] {
+        match self {
+            TileFlips::Off => &[(false, false)],
+            TileFlips::Horizontal => &[(false, false), (true, false)],
+            TileFlips::Both => &[(false, false), (true, false), (false, true), (true, true)],
+        }
+    }
+
+    /// Number of palettes a tilemap entry can select
+    pub fn max_palettes(self) -> usize {
+        match self {
+            TileFlips::Off | TileFlips::Horizontal => MAX_PALETTES,
+            TileFlips::Both => MAX_PALETTES / 2,
+        }
+    }
+}
+
+impl FromStr for TileFlips {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(TileFlips::Off),
+            "horizontal" => Ok(TileFlips::Horizontal),
+            "both" => Ok(TileFlips::Both),
+            _ => Err(format!("{s} is not one of off, horizontal or both")),
+        }
+    }
+}
+
+impl fmt::Display for TileFlips {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TileFlips::Off => "off",
+            TileFlips::Horizontal => "horizontal",
+            TileFlips::Both => "both",
+        };
+        f.write_str(name)
     }
 }
 
@@ -202,22 +600,41 @@ impl Palette {
 pub struct TilemapEntry {
     pub palette_index: usize,
     pub tile_index: usize,
+    #[serde(default)]
+    pub x_flip: bool,
+    #[serde(default)]
+    pub y_flip: bool,
     pub raw_value: u16,
 }
 
 impl TilemapEntry {
     /// Create a new tilemap entry with the given palette and tile indices
     pub fn new(palette_index: usize, tile_index: usize) -> Self {
+        Self::with_flip(palette_index, tile_index, false, false)
+    }
+
+    /// Create a new tilemap entry that mirrors the tile
+    pub fn with_flip(palette_index: usize, tile_index: usize, x_flip: bool, y_flip: bool) -> Self {
+        let mut raw_value = ((palette_index << PALETTE_INDEX_SHIFT) | tile_index) as u16;
+        if x_flip {
+            raw_value |= X_FLIP_BIT;
+        }
+        if y_flip {
+            raw_value |= Y_FLIP_BIT;
+        }
+
         TilemapEntry {
             palette_index,
             tile_index,
-            raw_value: ((palette_index << PALETTE_INDEX_SHIFT) | tile_index) as u16,
+            x_flip,
+            y_flip,
+            raw_value,
         }
     }
 }
 
 /// Represents a unique tile after clustering
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UniqueTile {
     /// The quantized pixel data
     pub quantized: Vec<u16>,
@@ -233,6 +650,53 @@ pub struct TileAssignment {
     pub unique_tile_index: usize,
     /// Palette index to use for this position
     pub palette_index: usize,
+    /// Whether the unique tile is mirrored horizontally here
+    pub x_flip: bool,
+    /// Whether the unique tile is mirrored vertically here
+    pub y_flip: bool,
+}
+
+/// Get the color index of a pixel in quantized tile data
+pub fn texel(quantized: &[u16], pixel_idx: usize) -> usize {
+    let chunk = quantized[pixel_idx / PIXELS_PER_CHUNK];
+    ((chunk >> ((pixel_idx % PIXELS_PER_CHUNK) * BITS_PER_COLOR)) & 0xF) as usize
+}
+
+/// Index of the pixel that lands at the given index when a tile is mirrored
+pub fn flipped_pixel(config: &Config, pixel_idx: usize, x_flip: bool, y_flip: bool) -> usize {
+    let width = config.tile_width as usize;
+    let height = config.tile_height as usize;
+    let (mut x, mut y) = (pixel_idx % width, pixel_idx / width);
+    if x_flip {
+        x = width - 1 - x;
+    }
+    if y_flip {
+        y = height - 1 - y;
+    }
+    y * width + x
+}
+
+/// Mirror quantized tile data horizontally and/or vertically
+pub fn flip_texels(quantized: &[u16], config: &Config, x_flip: bool, y_flip: bool) -> Vec<u16> {
+    let mut flipped = vec![0u16; quantized.len()];
+    for pixel_idx in 0..config.tile_size() {
+        let color_idx = texel(quantized, flipped_pixel(config, pixel_idx, x_flip, y_flip));
+        flipped[pixel_idx / PIXELS_PER_CHUNK] |=
+            (color_idx as u16) << ((pixel_idx % PIXELS_PER_CHUNK) * BITS_PER_COLOR);
+    }
+    flipped
+}
+
+/// Index of the used palette color nearest to a color
+fn nearest_color(color: Oklab, palette: &[ColorFrequency]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .filter(|(_, centre)| centre.frequency > 0)
+        .min_by(|(_, a), (_, b)| {
+            oklab_delta_e(color, a.color).total_cmp(&oklab_delta_e(color, b.color))
+        })
+        .map_or(0, |(index, _)| index)
 }
 
 /// Extract unique colors from a tile into a color frequency list
@@ -249,101 +713,254 @@ fn extract_colors(tile: &[Oklab], threshold: f32, colors: &mut Vec<ColorFrequenc
 /// Main struct for the image conversion process
 pub struct ImageConverter {
     config: Config,
+    dither_mask: Option<GrayImage>,
 }
 
 impl ImageConverter {
     /// Create a new image converter with the given configuration
     pub fn new(config: Config) -> Self {
-        ImageConverter { config }
+        ImageConverter {
+            config,
+            dither_mask: None,
+        }
     }
 
-    /// Main execution function to run the entire conversion process
-    pub fn convert(&self) -> Result<TilemapData, ConversionError> {
-        // Read the input image
-        let img = self.read_image()?;
-
-        // Extract tiles from the image
-        let raw_tiles = self.extract_tiles(&img)?;
-
-        // Generate palettes
-        let palettes = self.generate_palettes(&raw_tiles)?;
-
-        // Assign palettes to tiles (initial assignment for quantization)
-        let tile_palette_assignments = self.assign_palettes(&raw_tiles, &palettes)?;
-
-        // Quantize tiles with initial palette assignments
-        let quantized_tiles =
-            self.quantize_tiles(&raw_tiles, &palettes, &tile_palette_assignments)?;
+    /// Don't dither where the mask is black. The mask must be the size of the
+    /// image, and is fitted to the tilemap along with it. Only single image
+    /// conversions use it.
+    pub fn with_dither_mask(mut self, mask: GrayImage) -> Self {
+        self.dither_mask = Some(mask);
+        self
+    }
 
-        // Cluster quantized tiles to find unique representative tiles
-        let unique_tiles =
-            self.cluster_quantized_tiles(&quantized_tiles, &tile_palette_assignments, &palettes)?;
+    /// The configuration the converter uses
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 
-        // Find the best (unique_tile, palette) combination for each tilemap position
-        let tile_assignments = self.find_best_tile_assignments(&raw_tiles, &unique_tiles, &palettes);
+    /// Convert an image into palettes, unique tiles and a tilemap. Nothing
+    /// is written, see the `output` module for that.
+    ///
+    /// The image is first fitted to the tilemap as the configuration says,
+    /// and the returned configuration has the tilemap size that was used.
+    pub fn convert(&self, img: &DynamicImage) -> Result<TilemapData, ConversionError> {
+        let fitted = self.fit(img)?;
+        let mut tilemaps = self.convert_fitted(std::slice::from_ref(&fitted))?;
+        Ok(tilemaps.remove(0))
+    }
 
-        // Generate tilemap with tile indices and palette indices
-        let tilemap = self.generate_tilemap_from_assignments(&tile_assignments);
+    /// Fit an image, and the dither mask, to the tilemap as the configuration
+    /// says
+    ///
+    /// Returns the fitted image and a converter for it, whose configuration
+    /// has the tilemap size that was used. Pass them to
+    /// [`ImageConverter::convert_fitted`] to convert the image.
+    pub fn fit(
+        &self,
+        img: &DynamicImage,
+    ) -> Result<(DynamicImage, ImageConverter), ConversionError> {
+        // Check the settings, then fit the image to the tilemap
+        self.config.validate()?;
+        let (fitted, config) = fit::fit_image(img, &self.config)?;
+        config.validate()?;
+
+        // Fit the dither mask the same way
+        let mut converter = ImageConverter::new(config);
+        if let Some(mask) = &self.dither_mask {
+            if mask.dimensions() != img.dimensions() {
+                return Err(ConversionError::InvalidConfig(format!(
+                    "dither mask is {}x{} but the image is {}x{}",
+                    mask.width(),
+                    mask.height(),
+                    img.width(),
+                    img.height()
+                )));
+            }
+            let mask = DynamicImage::ImageLuma8(mask.clone());
+            let (mask, _) = fit::fit_image(&mask, &self.config)?;
+            converter = converter.with_dither_mask(mask.to_luma8());
+        }
 
-        // Write output files
-        self.write_palette_file(&palettes)?;
-        self.write_tilemap_file(&tilemap)?;
-        self.write_tiles_file(&unique_tiles)?;
+        Ok((fitted, converter))
+    }
 
-        // Generate output image using unique tiles and assignments
-        let output_img =
-            self.generate_output_image_from_assignments(&unique_tiles, &palettes, &tile_assignments)?;
+    /// Convert several images into one set of palettes and unique tiles, with
+    /// a tilemap for each image
+    ///
+    /// Each image is fitted to its own tilemap, so with the default fit mode
+    /// they can all be different sizes. The returned tilemaps all have the
+    /// same palettes and unique tiles.
+    pub fn convert_batch(
+        &self,
+        images: &[DynamicImage],
+    ) -> Result<Vec<TilemapData>, ConversionError> {
+        let fitted = self.fit_batch(images)?;
+        self.convert_fitted(&fitted)
+    }
 
-        // Create data for JSON output (using original quantized tiles for compatibility)
-        let tilemap_data = self.create_tilemap_data(raw_tiles, palettes, quantized_tiles, tilemap);
+    /// Fit several images to their own tilemaps, like
+    /// [`ImageConverter::fit`] but without the dither mask
+    pub fn fit_batch(
+        &self,
+        images: &[DynamicImage],
+    ) -> Result<Vec<(DynamicImage, ImageConverter)>, ConversionError> {
+        self.config.validate()?;
+        images
+            .iter()
+            .map(|img| {
+                let (img, config) = fit::fit_image(img, &self.config)?;
+                Ok((img, ImageConverter::new(config)))
+            })
+            .collect()
+    }
 
-        // Write JSON if requested
-        if let Some(json_path) = &self.config.output_json {
-            self.write_json_file(json_path, &tilemap_data)?;
+    /// Convert images that are already the size of their tilemaps, each with
+    /// a converter for the stages that depend on its tilemap size, as
+    /// [`ImageConverter::fit`] and [`ImageConverter::fit_batch`] return them
+    ///
+    /// All the images share one set of palettes and unique tiles, and each
+    /// gets its own tilemap.
+    pub fn convert_fitted(
+        &self,
+        images: &[(DynamicImage, ImageConverter)],
+    ) -> Result<Vec<TilemapData>, ConversionError> {
+        let (images, converters): (Vec<_>, Vec<_>) = images
+            .iter()
+            .map(|(img, converter)| (img, converter))
+            .unzip();
+
+        // Extract tiles from the images, and which of their pixels are opaque,
+        // into one list so the rest of the stages see every image at once
+        let mut raw_tiles = Vec::new();
+        let mut opaque = Vec::new();
+        let mut ranges = Vec::with_capacity(images.len());
+        for (converter, img) in converters.iter().zip(&images) {
+            let (tiles, tiles_opaque) = converter.extract_tiles(img)?;
+            ranges.push(raw_tiles.len()..raw_tiles.len() + tiles.len());
+            raw_tiles.extend(tiles);
+            opaque.extend(tiles_opaque);
         }
 
-        // Generate error metrics for the output image
-        self.generate_error_metrics(&output_img, &img)?;
+        let mut stats = ConversionStats {
+            tiles: raw_tiles.len(),
+            ..Default::default()
+        };
 
-        Ok(tilemap_data)
-    }
+        // Generate palettes
+        let palettes = self.generate_palettes(&raw_tiles, &opaque, &mut stats)?;
 
-    /// Read the input image
-    fn read_image(&self) -> Result<image::DynamicImage, ConversionError> {
-        let img = image::open(&self.config.input_file)?;
+        // Assign palettes to tiles (initial assignment for quantization)
+        let mut tile_palette_assignments = self.assign_palettes(&raw_tiles, &opaque, &palettes)?;
 
-        // Image must have width and height that are multiples of the tile size
-        if img.width() % self.config.tile_width != 0 || img.height() % self.config.tile_height != 0
-        {
-            return Err(ConversionError::InvalidDimensions(
-                img.width(),
-                img.height(),
-                self.config.tile_width,
-                self.config.tile_height,
-            ));
-        }
+        // Quantize tiles with initial palette assignments
+        let mut quantized_tiles = Self::quantize_images(
+            &converters,
+            &ranges,
+            &raw_tiles,
+            &opaque,
+            &palettes,
+            &tile_palette_assignments,
+        )?;
 
-        // Verify tilemap dimensions match image dimensions
-        let expected_width = self.config.total_width();
-        let expected_height = self.config.total_height();
+        // Cluster quantized tiles to find unique representative tiles
+        let mut unique_tiles = self.cluster_quantized_tiles(
+            &quantized_tiles,
+            &tile_palette_assignments,
+            &palettes,
+            &mut stats,
+        )?;
 
-        if img.width() != expected_width || img.height() != expected_height {
-            return Err(ConversionError::DimensionMismatch(
-                img.width(),
-                img.height(),
-                expected_width,
-                expected_height,
-            ));
+        // Find the best (unique_tile, palette) combination for each tilemap position
+        let mut tile_assignments =
+            self.find_best_tile_assignments(&raw_tiles, &opaque, &unique_tiles, &palettes, None);
+
+        // The best combination can be in another palette than the tile was
+        // dithered in, so the dither no longer matches what is displayed.
+        // Dither again in the palettes the tiles ended up with and pick the
+        // unique tiles again, until the palettes settle. The last pass keeps
+        // the palettes it dithered in, so every tile ends up shown in the
+        // palette it was dithered in.
+        let passes = self.config.redither_passes;
+        for pass in 1..=passes {
+            let final_palettes: Vec<usize> = tile_assignments
+                .iter()
+                .map(|assignment| assignment.palette_index)
+                .collect();
+            let changed = final_palettes
+                .iter()
+                .zip(&tile_palette_assignments)
+                .filter(|(a, b)| a != b)
+                .count();
+            if changed == 0 {
+                break;
+            }
+            stats.redither_changes.push(changed);
+
+            tile_palette_assignments = final_palettes;
+            quantized_tiles = Self::quantize_images(
+                &converters,
+                &ranges,
+                &raw_tiles,
+                &opaque,
+                &palettes,
+                &tile_palette_assignments,
+            )?;
+            unique_tiles = self.cluster_quantized_tiles(
+                &quantized_tiles,
+                &tile_palette_assignments,
+                &palettes,
+                &mut stats,
+            )?;
+            let pinned = (pass == passes).then_some(tile_palette_assignments.as_slice());
+            tile_assignments = self.find_best_tile_assignments(
+                &raw_tiles,
+                &opaque,
+                &unique_tiles,
+                &palettes,
+                pinned,
+            );
         }
 
-        Ok(img)
+        // Keep each tile's raw and quantized data and the palette it was
+        // dithered in
+        let tiles: Vec<Tile> = raw_tiles
+            .into_iter()
+            .zip(quantized_tiles)
+            .zip(tile_palette_assignments)
+            .map(|((pixels, quantized), palette)| Tile {
+                pixels,
+                quantized,
+                palette,
+            })
+            .collect();
+
+        // Split the results back up into each image's tilemap
+        Ok(converters
+            .iter()
+            .zip(ranges)
+            .map(|(converter, range)| {
+                converter.create_tilemap_data(
+                    tiles[range.clone()].to_vec(),
+                    palettes.clone(),
+                    unique_tiles.clone(),
+                    &tile_assignments[range],
+                    stats.clone(),
+                )
+            })
+            .collect())
     }
 
-    /// Extract tiles from the image
-    fn extract_tiles(&self, img: &image::DynamicImage) -> Result<Vec<Vec<Oklab>>, ConversionError> {
+    /// Extract tiles from the image, along with which of their pixels are
+    /// opaque. Every pixel is opaque unless transparency is enabled.
+    #[allow(clippy::type_complexity)]
+    fn extract_tiles(
+        &self,
+        img: &DynamicImage,
+    ) -> Result<(Vec<Vec<Oklab>>, Vec<Vec<bool>>), ConversionError> {
         let tile_size = self.config.tile_size();
         let total_tiles = self.config.total_tiles();
         let mut tiles = Vec::with_capacity(total_tiles);
+        let mut opaque = vec![vec![true; tile_size]; total_tiles];
 
         // Initialize tiles with empty vectors
         for _ in 0..total_tiles {
@@ -367,39 +984,82 @@ impl ImageConverter {
             let tile_index = (tile_map_y * self.config.tilemap_width + tile_map_x) as usize;
             let pixel_index = (tile_y * self.config.tile_width + tile_x) as usize;
             tiles[tile_index][pixel_index] = oklab;
+
+            if self.config.transparency && channels[3] < self.config.alpha_threshold {
+                opaque[tile_index][pixel_index] = false;
+            }
         }
 
-        Ok(tiles)
+        Ok((tiles, opaque))
     }
 
     /// Generate palettes from the tiles
-    fn generate_palettes(&self, tiles: &[Vec<Oklab>]) -> Result<Vec<Palette>, ConversionError> {
+    fn generate_palettes(
+        &self,
+        tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
+        stats: &mut ConversionStats,
+    ) -> Result<Vec<Palette>, ConversionError> {
         let tile_size = self.config.tile_size();
+        let locked_palettes = &self.config.locked_palettes;
+        let free_palettes = self.config.num_palettes - locked_palettes.len();
         let mut cluster_data = Vec::new();
 
-        // Prepare data for clustering
-        self.prepare_clustering_data(tiles, tile_size, &mut cluster_data);
-
-        // Perform k-means clustering to group tiles by color similarity
-        let kmean: KMeans<_, 8, _> = KMeans::new(
-            cluster_data,
-            tiles.len(),
-            tile_size * tile_size * 3,
-            OklabDistance,
-        );
+        // Tiles a locked palette already shows exactly don't need a generated
+        // palette, so they're left out like fully transparent tiles
+        let mut clustered_opaque = opaque.to_vec();
+        for (tile, tile_opaque) in tiles.iter().zip(clustered_opaque.iter_mut()) {
+            let threshold = self.config.color_similarity_threshold;
+            if locked_palettes
+                .iter()
+                .any(|palette| palette.matches_tile(tile, tile_opaque, threshold))
+            {
+                tile_opaque.fill(false);
+            }
+        }
 
-        let result = kmean.kmeans_lloyd(
-            self.config.num_palettes,
-            KMEANS_MAX_ITERATIONS,
-            KMeans::init_kmeanplusplus,
-            &KMeansConfig::default(),
-        );
+        // Prepare data for clustering, leaving out fully transparent tiles
+        let clustered_tiles =
+            self.prepare_clustering_data(tiles, &clustered_opaque, tile_size, &mut cluster_data);
 
         // Extract colors from each cluster to create palettes
-        let colors = self.extract_palette_colors(tiles, &result)?;
+        let colors = if clustered_tiles.is_empty() || free_palettes == 0 {
+            vec![Vec::new(); free_palettes]
+        } else {
+            // Perform k-means clustering to group tiles by color similarity
+            let kmean: KMeans<_, 8, _> = KMeans::new(
+                cluster_data,
+                clustered_tiles.len(),
+                tile_size * tile_size * 3,
+                OklabDistance,
+            );
+
+            let result = kmean.kmeans_lloyd(
+                free_palettes.min(clustered_tiles.len()),
+                KMEANS_MAX_ITERATIONS,
+                KMeans::init_kmeanplusplus,
+                &KMeansConfig::default(),
+            );
+
+            self.extract_palette_colors(
+                tiles,
+                &clustered_opaque,
+                &clustered_tiles,
+                &result,
+                free_palettes,
+            )?
+        };
 
         // Process each palette to ensure it has the right number of colors
-        let mut palettes = self.process_palettes(colors)?;
+        let mut palettes = self.process_palettes(colors, stats)?;
+
+        // Everything after this works with the colors the hardware can show.
+        // Reduced palettes already are, but small ones were never reduced.
+        for palette in palettes.iter_mut() {
+            for color in palette.colors.iter_mut() {
+                color.color = self.config.color_depth.reduce(color.color);
+            }
+        }
 
         // Sort palettes by average luminance for better visual organization
         palettes.sort_by(|a, b| {
@@ -408,26 +1068,50 @@ impl ImageConverter {
                 .unwrap()
         });
 
-        // fix palette 0, index 0 to be black
-        palettes.get_mut(0).map(|p| {
-            if let Some(color) = p.colors.get_mut(0) {
+        // fix palette 0, index 0 to be black, unless palette 0 is locked
+        if locked_palettes.is_empty() {
+            if let Some(color) = palettes.get_mut(0).and_then(|p| p.colors.get_mut(0)) {
                 color.color = Oklab::from_rgb(0, 0, 0);
             }
-        });
+        }
 
-        Ok(palettes)
+        // Locked palettes come first, in the order they were given, and are
+        // used unchanged
+        Ok(locked_palettes.iter().cloned().chain(palettes).collect())
     }
 
-    /// Prepare data for k-means clustering
+    /// Prepare data for k-means clustering, returning the indices of the
+    /// tiles that were included
+    ///
+    /// Only opaque pixels are clustered. Tiles with transparent pixels repeat
+    /// their opaque ones to fill the gaps, and fully transparent tiles are
+    /// left out.
     fn prepare_clustering_data(
         &self,
         tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
         tile_size: usize,
         cluster_data: &mut Vec<f32>,
-    ) {
-        for tile in tiles.iter() {
-            let mut hue_sorted = tile.clone();
+    ) -> Vec<usize> {
+        let mut clustered_tiles = Vec::with_capacity(tiles.len());
+
+        for (tile_index, tile) in tiles.iter().enumerate() {
+            let mut hue_sorted: Vec<Oklab> = tile
+                .iter()
+                .zip(&opaque[tile_index])
+                .filter(|(_, &opaque)| opaque)
+                .map(|(color, _)| *color)
+                .collect();
+            if hue_sorted.is_empty() {
+                continue;
+            }
+            clustered_tiles.push(tile_index);
+
             hue_sorted.sort_by(|a, b| a.hue().partial_cmp(&b.hue()).unwrap());
+            let opaque_pixels = hue_sorted.len();
+            hue_sorted = (0..tile_size)
+                .map(|i| hue_sorted[i % opaque_pixels])
+                .collect();
 
             // Store every permutation of the tile in the cluster data
             for offset in 0..tile_size {
@@ -446,40 +1130,46 @@ impl ImageConverter {
                 }
             }
         }
+
+        clustered_tiles
     }
 
-    /// Extract colors for each palette from the clustered tiles
+    /// Extract the opaque colors for each palette from the clustered tiles
     fn extract_palette_colors(
         &self,
         tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
+        clustered_tiles: &[usize],
         clustering_result: &kmeans::KMeansState<f32>,
+        num_palettes: usize,
     ) -> Result<Vec<Vec<ColorFrequency>>, ConversionError> {
-        let mut colors = vec![Vec::new(); self.config.num_palettes];
-
-        for y in 0..self.config.tilemap_height {
-            for x in 0..self.config.tilemap_width {
-                let tile_index = (y * self.config.tilemap_width + x) as usize;
-                if tile_index >= clustering_result.assignments.len() {
-                    return Err(ConversionError::PaletteGeneration(format!(
-                        "Tile index {} out of bounds for assignments",
-                        tile_index
-                    )));
-                }
+        let mut colors = vec![Vec::new(); num_palettes];
 
-                let assignment = clustering_result.assignments[tile_index];
-                if assignment >= self.config.num_palettes {
-                    return Err(ConversionError::PaletteGeneration(format!(
-                        "Palette assignment {} exceeds num_palettes {}",
-                        assignment, self.config.num_palettes
-                    )));
-                }
+        for (i, &tile_index) in clustered_tiles.iter().enumerate() {
+            if i >= clustering_result.assignments.len() {
+                return Err(ConversionError::PaletteGeneration(format!(
+                    "Tile index {tile_index} out of bounds for assignments"
+                )));
+            }
 
-                extract_colors(
-                    &tiles[tile_index],
-                    self.config.color_similarity_threshold,
-                    &mut colors[assignment],
-                );
+            let assignment = clustering_result.assignments[i];
+            if assignment >= num_palettes {
+                return Err(ConversionError::PaletteGeneration(format!(
+                    "Palette assignment {assignment} exceeds num_palettes {num_palettes}"
+                )));
             }
+
+            let opaque_pixels: Vec<Oklab> = tiles[tile_index]
+                .iter()
+                .zip(&opaque[tile_index])
+                .filter(|(_, &opaque)| opaque)
+                .map(|(color, _)| *color)
+                .collect();
+            extract_colors(
+                &opaque_pixels,
+                self.config.color_similarity_threshold,
+                &mut colors[assignment],
+            );
         }
 
         Ok(colors)
@@ -489,35 +1179,42 @@ impl ImageConverter {
     fn process_palettes(
         &self,
         colors: Vec<Vec<ColorFrequency>>,
+        stats: &mut ConversionStats,
     ) -> Result<Vec<Palette>, ConversionError> {
-        let mut palettes = Vec::with_capacity(self.config.num_palettes);
-        let mut min_colors = usize::MAX;
-        let mut max_colors = 0;
+        let mut palettes = Vec::with_capacity(colors.len());
+        stats.min_palette_colors = colors.iter().map(Vec::len).min().unwrap_or(0);
+        stats.max_palette_colors = colors.iter().map(Vec::len).max().unwrap_or(0);
 
         for mut color_frequencies in colors {
-            let num_colors = color_frequencies.len();
-            min_colors = min_colors.min(num_colors);
-            max_colors = max_colors.max(num_colors);
-
             // Sort by frequency (most frequent first)
             color_frequencies.sort_by(|a, b| b.frequency.cmp(&a.frequency));
 
             // If there are more colors than allowed, reduce using k-means
-            let processed_colors = if color_frequencies.len() > self.config.colors_per_palette {
-                self.reduce_colors(color_frequencies)?
-            } else {
-                color_frequencies
-            };
+            let processed_colors =
+                if color_frequencies.len() > self.config.opaque_colors_per_palette() {
+                    self.reduce_colors(color_frequencies)?
+                } else {
+                    color_frequencies
+                };
 
             // Create a new palette with the processed colors
             let mut palette = Palette {
                 colors: processed_colors,
+                transparent: false,
             };
             palette.sort_by_luminance();
+
+            // Put the transparent color in front, black like the cleared line buffer
+            if self.config.transparency {
+                palette.colors.insert(
+                    TRANSPARENT_INDEX,
+                    ColorFrequency::new(Oklab::from_rgb(0, 0, 0), 0),
+                );
+                palette.transparent = true;
+            }
             palettes.push(palette);
         }
 
-        println!("min_colors: {}, max_colors: {}", min_colors, max_colors);
         Ok(palettes)
     }
 
@@ -539,17 +1236,46 @@ impl ImageConverter {
             KMeans::new(cluster_data, color_frequencies.len(), 3, OklabDistance);
 
         let result = kmean.kmeans_lloyd(
-            self.config.colors_per_palette,
+            self.config.opaque_colors_per_palette(),
             COLOR_REDUCTION_MAX_ITERATIONS,
             KMeans::init_kmeanplusplus,
             &KMeansConfig::default(),
         );
 
-        // Calculate new representative colors by weighted averaging
-        let mut new_colors = vec![ColorFrequency::default(); self.config.colors_per_palette];
+        let mut assignments = result.assignments;
+        let mut new_colors = self.reduced_centres(&color_frequencies, &assignments);
+
+        // At a lower color depth the centres were snapped to colors the
+        // hardware can show, so keep assigning colors to the snapped centres
+        // until they settle, rather than rounding a full precision result
+        if !self.config.color_depth.is_full() {
+            for _ in 0..COLOR_DEPTH_MAX_ITERATIONS {
+                let next: Vec<usize> = color_frequencies
+                    .iter()
+                    .map(|color| nearest_color(color.color, &new_colors))
+                    .collect();
+                if next == assignments {
+                    break;
+                }
+                assignments = next;
+                new_colors = self.reduced_centres(&color_frequencies, &assignments);
+            }
+        }
+
+        Ok(new_colors)
+    }
+
+    /// The frequency weighted mean of the colors assigned to each palette
+    /// color, reduced to the color depth
+    fn reduced_centres(
+        &self,
+        color_frequencies: &[ColorFrequency],
+        assignments: &[usize],
+    ) -> Vec<ColorFrequency> {
+        let mut new_colors =
+            vec![ColorFrequency::default(); self.config.opaque_colors_per_palette()];
 
-        for (i, color) in color_frequencies.iter().enumerate() {
-            let assignment = result.assignments[i];
+        for (color, &assignment) in color_frequencies.iter().zip(assignments) {
             // Accumulate weighted components
             new_colors[assignment].color.l += color.color.l * color.frequency as f32;
             new_colors[assignment].color.a += color.color.a * color.frequency as f32;
@@ -562,37 +1288,37 @@ impl ImageConverter {
             color.color.l /= color.frequency as f32;
             color.color.a /= color.frequency as f32;
             color.color.b /= color.frequency as f32;
+            color.color = self.config.color_depth.reduce(color.color);
         }
 
-        Ok(new_colors)
+        new_colors
     }
 
     /// Assign palettes to tiles
     fn assign_palettes(
         &self,
         tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
         palettes: &[Palette],
     ) -> Result<Vec<usize>, ConversionError> {
-        let total_tiles = self.config.total_tiles();
-        let mut tile_palette = Vec::with_capacity(total_tiles);
+        let mut tile_palette = Vec::with_capacity(tiles.len());
 
         // Find the best palette for each tile
-        for y in 0..self.config.tilemap_height {
-            for x in 0..self.config.tilemap_width {
-                let tile_index = (y * self.config.tilemap_width + x) as usize;
-                let palette_index = self.find_best_palette_for_tile(tiles, palettes, tile_index);
+        for tile_index in 0..tiles.len() {
+            let palette_index =
+                self.find_best_palette_for_tile(tiles, opaque, palettes, tile_index);
 
-                tile_palette.push(palette_index);
-            }
+            tile_palette.push(palette_index);
         }
 
         Ok(tile_palette)
     }
 
-    /// Find the best palette for a specific tile
+    /// Find the best palette for a specific tile, by its opaque pixels
     fn find_best_palette_for_tile(
         &self,
         tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
         palettes: &[Palette],
         tile_index: usize,
     ) -> usize {
@@ -601,9 +1327,13 @@ impl ImageConverter {
 
         for (i, palette) in palettes.iter().enumerate() {
             let mut error = 0.0;
-            for color in tiles[tile_index].iter() {
+            let opaque_pixels = tiles[tile_index]
+                .iter()
+                .zip(&opaque[tile_index])
+                .filter(|(_, &opaque)| opaque);
+            for (color, _) in opaque_pixels {
                 let mut min_delta_e = f32::MAX;
-                for palette_color in palette.colors.iter() {
+                for palette_color in palette.opaque_colors() {
                     let delta_e = oklab_delta_e(*color, palette_color.color);
                     min_delta_e = min_delta_e.min(delta_e);
                 }
@@ -625,16 +1355,21 @@ impl ImageConverter {
         quantized_tiles: &[Vec<u16>],
         tile_palette_assignments: &[usize],
         palettes: &[Palette],
+        stats: &mut ConversionStats,
     ) -> Result<Vec<UniqueTile>, ConversionError> {
-        let num_tiles = quantized_tiles.len();
+        // Duplicates, and mirror images that tilemap entries can flip back, need
+        // no tile of their own
+        let candidates = self.distinct_tiles(quantized_tiles);
+        let num_tiles = candidates.len();
+        stats.distinct_tiles = num_tiles;
+        stats.unique_tiles = num_tiles.min(self.config.max_unique_tiles);
 
         // If under max, no clustering needed - each tile is unique
         if num_tiles <= self.config.max_unique_tiles {
-            return Ok(quantized_tiles
+            return Ok(candidates
                 .iter()
-                .enumerate()
-                .map(|(i, q)| UniqueTile {
-                    quantized: q.clone(),
+                .map(|&i| UniqueTile {
+                    quantized: quantized_tiles[i].clone(),
                     source_tile: i,
                 })
                 .collect());
@@ -645,19 +1380,23 @@ impl ImageConverter {
         let feature_size = tile_size * 3; // 3 LAB components per pixel
         let mut cluster_data = Vec::with_capacity(num_tiles * feature_size);
 
-        for (tile_idx, tile) in quantized_tiles.iter().enumerate() {
+        for &tile_idx in candidates.iter() {
+            let tile = &quantized_tiles[tile_idx];
             let palette = &palettes[tile_palette_assignments[tile_idx]];
 
             // Convert each pixel to Oklab color
-            for chunk_idx in 0..(tile_size / PIXELS_PER_CHUNK) {
+            for chunk in tile.iter().take(tile_size / PIXELS_PER_CHUNK) {
                 for pixel_offset in 0..PIXELS_PER_CHUNK {
-                    let color_idx =
-                        ((tile[chunk_idx] >> (pixel_offset * BITS_PER_COLOR)) & 0xF) as usize;
-                    let color = palette
-                        .colors
-                        .get(color_idx)
-                        .map(|c| c.color)
-                        .unwrap_or_else(|| Oklab::new(0.0, 0.0, 0.0));
+                    let color_idx = ((chunk >> (pixel_offset * BITS_PER_COLOR)) & 0xF) as usize;
+                    let color = if palette.is_transparent(color_idx) {
+                        Oklab::new(TRANSPARENT_LIGHTNESS, 0.0, 0.0)
+                    } else {
+                        palette
+                            .colors
+                            .get(color_idx)
+                            .map(|c| c.color)
+                            .unwrap_or_else(|| Oklab::new(0.0, 0.0, 0.0))
+                    };
                     cluster_data.push(color.l);
                     cluster_data.push(color.a);
                     cluster_data.push(color.b);
@@ -680,7 +1419,7 @@ impl ImageConverter {
         let mut unique_tiles = Vec::with_capacity(self.config.max_unique_tiles);
         let mut used_clusters = vec![false; self.config.max_unique_tiles];
 
-        for (tile_idx, &cluster_id) in result.assignments.iter().enumerate() {
+        for (&tile_idx, &cluster_id) in candidates.iter().zip(result.assignments.iter()) {
             if !used_clusters[cluster_id] {
                 used_clusters[cluster_id] = true;
                 unique_tiles.push(UniqueTile {
@@ -689,60 +1428,103 @@ impl ImageConverter {
                 });
             }
         }
-
-        println!(
-            "Clustered {} tiles into {} unique tiles",
-            num_tiles,
-            unique_tiles.len()
-        );
+        stats.unique_tiles = unique_tiles.len();
 
         Ok(unique_tiles)
     }
 
-    /// Find the best (unique_tile, palette) combination for each tilemap position
+    /// Indices of the quantized tiles that aren't an exact copy of an earlier
+    /// one, or a mirror image of one for the flips the tilemap entries can
+    /// encode. Texels are palette-relative, so copies in other palettes count.
+    fn distinct_tiles(&self, quantized_tiles: &[Vec<u16>]) -> Vec<usize> {
+        let flips = &self.config.tile_flips.variants()[1..];
+        let mut seen: HashSet<&[u16]> = HashSet::new();
+        let mut tiles = Vec::with_capacity(quantized_tiles.len());
+
+        for (tile_idx, tile) in quantized_tiles.iter().enumerate() {
+            let duplicate = seen.contains(tile.as_slice())
+                || flips.iter().any(|&(x_flip, y_flip)| {
+                    seen.contains(flip_texels(tile, &self.config, x_flip, y_flip).as_slice())
+                });
+            if !duplicate {
+                seen.insert(tile);
+                tiles.push(tile_idx);
+            }
+        }
+
+        tiles
+    }
+
+    /// Find the best (unique_tile, palette) combination for each tilemap
+    /// position, only trying each position's own palette if they are pinned
     fn find_best_tile_assignments(
         &self,
         raw_tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
         unique_tiles: &[UniqueTile],
         palettes: &[Palette],
+        pinned_palettes: Option<&[usize]>,
     ) -> Vec<TileAssignment> {
         let mut assignments = Vec::with_capacity(raw_tiles.len());
 
-        for original_tile in raw_tiles.iter() {
+        // Every way a tilemap entry can show each unique tile
+        let mut variants = Vec::new();
+        for (unique_idx, unique_tile) in unique_tiles.iter().enumerate() {
+            for &(x_flip, y_flip) in self.config.tile_flips.variants() {
+                let quantized = flip_texels(&unique_tile.quantized, &self.config, x_flip, y_flip);
+                variants.push((unique_idx, x_flip, y_flip, quantized));
+            }
+        }
+
+        for (tile_idx, (original_tile, original_opaque)) in raw_tiles.iter().zip(opaque).enumerate()
+        {
             let mut best_error = f32::MAX;
-            let mut best_unique_idx = 0;
-            let mut best_palette_idx = 0;
+            let mut best = TileAssignment {
+                unique_tile_index: 0,
+                palette_index: 0,
+                x_flip: false,
+                y_flip: false,
+            };
 
-            // Try each unique tile with each palette
-            for (unique_idx, unique_tile) in unique_tiles.iter().enumerate() {
+            // Try each unique tile, flipped each way, with each palette
+            for (unique_idx, x_flip, y_flip, quantized) in variants.iter() {
                 for (palette_idx, palette) in palettes.iter().enumerate() {
+                    if pinned_palettes.is_some_and(|pinned| pinned[tile_idx] != palette_idx) {
+                        continue;
+                    }
                     let error = self.calculate_reconstruction_error(
                         original_tile,
-                        &unique_tile.quantized,
+                        original_opaque,
+                        quantized,
                         palette,
                     );
 
                     if error < best_error {
                         best_error = error;
-                        best_unique_idx = unique_idx;
-                        best_palette_idx = palette_idx;
+                        best = TileAssignment {
+                            unique_tile_index: *unique_idx,
+                            palette_index: palette_idx,
+                            x_flip: *x_flip,
+                            y_flip: *y_flip,
+                        };
                     }
                 }
             }
 
-            assignments.push(TileAssignment {
-                unique_tile_index: best_unique_idx,
-                palette_index: best_palette_idx,
-            });
+            assignments.push(best);
         }
 
         assignments
     }
 
     /// Calculate reconstruction error between original tile and quantized representation
+    ///
+    /// Transparent pixels must stay transparent and opaque pixels opaque, and
+    /// get the same penalty as a missing color otherwise.
     fn calculate_reconstruction_error(
         &self,
         original: &[Oklab],
+        opaque: &[bool],
         quantized: &[u16],
         palette: &Palette,
     ) -> f32 {
@@ -755,7 +1537,13 @@ impl ImageConverter {
             let color_idx =
                 ((quantized[chunk_idx] >> (pixel_offset * BITS_PER_COLOR)) & 0xF) as usize;
 
-            if let Some(palette_color) = palette.colors.get(color_idx) {
+            let transparent = palette.is_transparent(color_idx);
+            if transparent || !opaque[pixel_idx] {
+                // A transparent pixel must map to the transparent color and vice versa
+                if transparent == opaque[pixel_idx] {
+                    total_error += 1.0;
+                }
+            } else if let Some(palette_color) = palette.colors.get(color_idx) {
                 total_error += oklab_delta_e(original_color, palette_color.color);
             } else {
                 total_error += 1.0; // Penalty for missing color
@@ -765,10 +1553,33 @@ impl ImageConverter {
         total_error
     }
 
+    /// Quantize the tiles of every image based on assigned palettes, dithering
+    /// each image separately with its own converter
+    fn quantize_images(
+        converters: &[&ImageConverter],
+        ranges: &[Range<usize>],
+        tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
+        palettes: &[Palette],
+        tile_palette_assignments: &[usize],
+    ) -> Result<Vec<Vec<u16>>, ConversionError> {
+        let mut quantized_tiles = Vec::with_capacity(tiles.len());
+        for (converter, range) in converters.iter().zip(ranges) {
+            quantized_tiles.extend(converter.quantize_tiles(
+                &tiles[range.clone()],
+                &opaque[range.clone()],
+                palettes,
+                &tile_palette_assignments[range.clone()],
+            )?);
+        }
+        Ok(quantized_tiles)
+    }
+
     /// Quantize tiles based on assigned palettes
     fn quantize_tiles(
         &self,
         tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
         palettes: &[Palette],
         tile_palette_assignments: &[usize],
     ) -> Result<Vec<Vec<u16>>, ConversionError> {
@@ -787,33 +1598,122 @@ impl ImageConverter {
             quantized_tiles.push(vec![0u16; chunks_per_tile]);
         }
 
-        // Process each pixel row by row for better cache locality
+        // Process each pixel row by row for better cache locality, skipping
+        // tiles that repeat an earlier one
+        let repeats = self.repeated_tiles(tiles, opaque, tile_palette_assignments);
         self.quantize_pixels(
             tiles,
+            opaque,
             palettes,
             tile_palette_assignments,
+            &repeats,
             &mut quantized_tiles,
             &mut dither_error,
         )?;
 
+        // Repeats copy the earlier tile, so they stay duplicates however the
+        // error diffused around them
+        for (tile_index, repeat) in repeats.iter().enumerate() {
+            if let Some((earlier, x_flip, y_flip)) = *repeat {
+                quantized_tiles[tile_index] =
+                    flip_texels(&quantized_tiles[earlier], &self.config, x_flip, y_flip);
+            }
+        }
+
         Ok(quantized_tiles)
     }
 
+    /// For each tile whose source pixels repeat an earlier tile's, or mirror
+    /// them for the flips the tilemap entries can encode, the earlier tile
+    /// and how it is mirrored
+    ///
+    /// Error diffusion carries error across tile borders, so repeated tiles
+    /// would rarely dither the same and couldn't share a unique tile. They
+    /// also need the same palette, transparent pixels and dither mask.
+    fn repeated_tiles(
+        &self,
+        tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
+        tile_palette_assignments: &[usize],
+    ) -> Vec<Option<(usize, bool, bool)>> {
+        let tile_width = self.config.tile_width as usize;
+        let tile_height = self.config.tile_height as usize;
+        let tilemap_width = self.config.tilemap_width as usize;
+        let mut seen: HashMap<Vec<u32>, usize> = HashMap::new();
+        let mut repeats = Vec::with_capacity(tiles.len());
+
+        for (tile_index, tile) in tiles.iter().enumerate() {
+            let x = tile_index % tilemap_width * tile_width;
+            let y = tile_index / tilemap_width * tile_height;
+
+            // The tile's pixels in mirrored order, with whether they dither
+            let key = |x_flip, y_flip| {
+                let mut key = vec![tile_palette_assignments[tile_index] as u32];
+                for pixel_idx in 0..tile.len() {
+                    let i = flipped_pixel(&self.config, pixel_idx, x_flip, y_flip);
+                    if !opaque[tile_index][i] {
+                        key.extend([0; 4]);
+                        continue;
+                    }
+                    let dither = self.config.dithering
+                        && self.dithers_pixel(x + i % tile_width, y + i / tile_width);
+                    let color = tile[i];
+                    key.extend([
+                        1 + dither as u32,
+                        color.l.to_bits(),
+                        color.a.to_bits(),
+                        color.b.to_bits(),
+                    ]);
+                }
+                key
+            };
+
+            let repeat = self
+                .config
+                .tile_flips
+                .variants()
+                .iter()
+                .find_map(|&(x_flip, y_flip)| {
+                    seen.get(&key(x_flip, y_flip))
+                        .map(|&earlier| (earlier, x_flip, y_flip))
+                });
+            if repeat.is_none() {
+                seen.insert(key(false, false), tile_index);
+            }
+            repeats.push(repeat);
+        }
+
+        repeats
+    }
+
     /// Quantize all pixels and apply dithering if enabled
+    #[allow(clippy::too_many_arguments)]
     fn quantize_pixels(
         &self,
         tiles: &[Vec<Oklab>],
+        opaque: &[Vec<bool>],
         palettes: &[Palette],
         tile_palette_assignments: &[usize],
+        repeats: &[Option<(usize, bool, bool)>],
         quantized_tiles: &mut [Vec<u16>],
         dither_error: &mut [Oklab],
     ) -> Result<(), ConversionError> {
         let img_width = self.config.total_width() as usize;
+        let kernel = self.config.dither_method.kernel();
+        let threshold_map = self.config.dither_method.threshold_map(
+            self.config.tile_width as usize,
+            self.config.tile_height as usize,
+        );
 
         for y in 0..self.config.tilemap_height {
             for ty in 0..self.config.tile_height {
                 for x in 0..self.config.tilemap_width {
                     let tile_index = (y * self.config.tilemap_width + x) as usize;
+                    // Repeats are copied afterwards, and the error diffused
+                    // into them is dropped
+                    if repeats[tile_index].is_some() {
+                        continue;
+                    }
                     let palette_idx = tile_palette_assignments[tile_index];
                     let palette = &palettes[palette_idx];
                     let out_tile = &mut quantized_tiles[tile_index];
@@ -823,24 +1723,37 @@ impl ImageConverter {
                         let gy = (y * self.config.tile_height + ty) as usize;
                         let gx = (x * self.config.tile_width + tx) as usize;
 
-                        // Get original color, add dithering error if enabled
-                        let color = if self.config.dithering {
-                            tiles[tile_index][i].add(&dither_error[gy * img_width + gx])
-                        } else {
-                            tiles[tile_index][i]
+                        // Transparent pixels keep index 0 and take no dithering error
+                        if !opaque[tile_index][i] {
+                            continue;
+                        }
+
+                        // Get original color, add diffused error if dithering here
+                        let dither = self.config.dithering && self.dithers_pixel(gx, gy);
+                        let color = match kernel {
+                            Some(_) if dither => {
+                                tiles[tile_index][i].add(&dither_error[gy * img_width + gx])
+                            }
+                            _ => tiles[tile_index][i],
                         };
 
-                        // Find closest color in palette
-                        let min_index = palette.find_best_color(color);
+                        // Find closest color in palette, or the ordered dither's pick
+                        let min_index = match threshold_map {
+                            Some(ref map) if dither => {
+                                self.ordered_color(palette, color, map, gx, gy)
+                            }
+                            _ => palette.find_best_color(color),
+                        };
 
                         // Set color index in output tile
                         let chunk_idx = i / PIXELS_PER_CHUNK;
                         let pixel_pos = i % PIXELS_PER_CHUNK;
                         out_tile[chunk_idx] |= (min_index as u16) << (pixel_pos * BITS_PER_COLOR);
 
-                        // Apply dithering if enabled
-                        if self.config.dithering {
-                            self.apply_sierra_dithering(
+                        // Diffuse the error if dithering here
+                        if let Some(kernel) = kernel.filter(|_| dither) {
+                            self.diffuse_error(
+                                kernel,
                                 dither_error,
                                 color,
                                 palette.colors[min_index].color,
@@ -857,9 +1770,19 @@ impl ImageConverter {
         Ok(())
     }
 
-    /// Apply Sierra dithering algorithm to distribute quantization error
-    fn apply_sierra_dithering(
+    /// Whether the dither mask allows dithering a pixel
+    fn dithers_pixel(&self, x: usize, y: usize) -> bool {
+        self.dither_mask
+            .as_ref()
+            .is_none_or(|mask| mask.get_pixel(x as u32, y as u32).0[0] >= 128)
+    }
+
+    /// Distribute a pixel's quantization error to its neighbours with an
+    /// error diffusion kernel
+    #[allow(clippy::too_many_arguments)]
+    fn diffuse_error(
         &self,
+        kernel: &DiffusionKernel,
         error: &mut [Oklab],
         original: Oklab,
         quantized: Oklab,
@@ -869,333 +1792,505 @@ impl ImageConverter {
     ) {
         let img_height = self.config.total_height() as usize;
         let diff =
-            original.dither_error_term(&quantized, self.config.dither_factor, DITHER_ERROR_DIVISOR);
+            original.dither_error_term(&quantized, self.config.dither_factor, kernel.divisor);
 
-        // Sierra dithering pattern - Apply error diffusion to neighboring pixels
-        // This is the classic Sierra filter pattern with 16 coefficients
-
-        // Current row
-        if x < width - 1 {
-            error[y * width + x + 1].weighted_add(&diff, 5.0);
-        }
-        if x < width - 2 {
-            error[y * width + x + 2].weighted_add(&diff, 3.0);
-        }
-
-        // Next row
-        if y < img_height - 1 {
-            if x > 1 {
-                error[(y + 1) * width + x - 2].weighted_add(&diff, 2.0);
-            }
-            if x > 0 {
-                error[(y + 1) * width + x - 1].weighted_add(&diff, 4.0);
-            }
-            error[(y + 1) * width + x].weighted_add(&diff, 5.0);
-            if x < width - 1 {
-                error[(y + 1) * width + x + 1].weighted_add(&diff, 4.0);
-            }
-            if x < width - 2 {
-                error[(y + 1) * width + x + 2].weighted_add(&diff, 2.0);
+        for &(dx, dy, weight) in kernel.taps {
+            let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+                continue;
+            };
+            if nx < width && ny < img_height {
+                error[ny * width + nx].weighted_add(&diff, weight);
             }
         }
+    }
 
-        // Two rows below
-        if y < img_height - 2 {
-            if x > 0 {
-                error[(y + 2) * width + x - 1].weighted_add(&diff, 2.0);
-            }
-            error[(y + 2) * width + x].weighted_add(&diff, 3.0);
-            if x < width - 1 {
-                error[(y + 2) * width + x + 1].weighted_add(&diff, 2.0);
-            }
+    /// Pick a palette color for ordered dithering
+    ///
+    /// This builds a mix of palette colors that averages out to the color,
+    /// then the threshold picks one of them, darkest first (Knoll's pattern
+    /// dithering). That works with any palette, unlike adding the threshold
+    /// to the color.
+    fn ordered_color(
+        &self,
+        palette: &Palette,
+        color: Oklab,
+        threshold_map: &ThresholdMap,
+        x: usize,
+        y: usize,
+    ) -> usize {
+        let factor = self.config.dither_factor;
+        let mut error = Oklab::new(0.0, 0.0, 0.0);
+        let mut candidates = Vec::with_capacity(ORDERED_DITHER_CANDIDATES);
+
+        for _ in 0..ORDERED_DITHER_CANDIDATES {
+            let attempt = Oklab::new(
+                color.l + error.l * factor,
+                color.a + error.a * factor,
+                color.b + error.b * factor,
+            );
+            let index = palette.find_best_color(attempt);
+            let Some(chosen) = palette.colors.get(index) else {
+                return index;
+            };
+            error.weighted_add(&color.dither_error_term(&chosen.color, 1.0, 1.0), 1.0);
+            candidates.push(index);
         }
+
+        candidates.sort_by(|&a, &b| {
+            palette.colors[a]
+                .color
+                .l
+                .partial_cmp(&palette.colors[b].color.l)
+                .unwrap()
+        });
+        let pick = (threshold_map.threshold(x, y) * ORDERED_DITHER_CANDIDATES as f32) as usize;
+        candidates[pick.min(ORDERED_DITHER_CANDIDATES - 1)]
     }
 
-    /// Generate tilemap from tile assignments (with unique tile indices)
-    fn generate_tilemap_from_assignments(&self, tile_assignments: &[TileAssignment]) -> Vec<u16> {
-        tile_assignments
+    /// Gather everything the conversion produced
+    fn create_tilemap_data(
+        &self,
+        tiles: Vec<Tile>,
+        palettes: Vec<Palette>,
+        unique_tiles: Vec<UniqueTile>,
+        tile_assignments: &[TileAssignment],
+        stats: ConversionStats,
+    ) -> TilemapData {
+        // Create tilemap entries with unique tile indices
+        let tilemap: Vec<TilemapEntry> = tile_assignments
             .iter()
             .map(|assignment| {
-                TilemapEntry::new(assignment.palette_index, assignment.unique_tile_index).raw_value
+                TilemapEntry::with_flip(
+                    assignment.palette_index,
+                    assignment.unique_tile_index,
+                    assignment.x_flip,
+                    assignment.y_flip,
+                )
             })
-            .collect()
-    }
-
-    /// Write palette data to hex file
-    fn write_palette_file(&self, palettes: &[Palette]) -> Result<(), ConversionError> {
-        let mut palette_file = File::create(&self.config.output_palette_hex)?;
-
-        for palette in palettes.iter() {
-            for color in palette.colors.iter() {
-                let (r, g, b) = color.color.to_rgb();
-                write!(&mut palette_file, "{:02x}{:02x}{:02x} ", r, g, b)?;
-            }
+            .collect();
 
-            // Pad with zeros for missing colors
-            for _ in palette.colors.len()..self.config.colors_per_palette {
-                write!(&mut palette_file, "000000 ")?;
-            }
-            writeln!(&mut palette_file)?;
+        TilemapData {
+            config: self.config.clone(),
+            tiles,
+            palettes,
+            tilemap,
+            unique_tiles,
+            stats,
         }
-
-        Ok(())
     }
+}
 
-    /// Write tilemap data to hex file
-    fn write_tilemap_file(&self, tilemap: &[u16]) -> Result<(), ConversionError> {
-        let mut tile_map_file = File::create(&self.config.output_tilemap_hex)?;
+/// Convert an image into palettes, unique tiles and a tilemap with the given
+/// configuration, without reading or writing any files
+pub fn convert(img: &DynamicImage, config: &Config) -> Result<TilemapData, ConversionError> {
+    ImageConverter::new(config.clone()).convert(img)
+}
 
-        for (i, item) in tilemap.iter().enumerate() {
-            write!(&mut tile_map_file, "{:04x} ", item)?;
-            if i % self.config.tilemap_width as usize == (self.config.tilemap_width as usize) - 1 {
-                writeln!(&mut tile_map_file)?;
-            }
-        }
+/// Convert several images into shared palettes and unique tiles, with a
+/// tilemap for each, without reading or writing any files
+pub fn convert_batch(
+    images: &[DynamicImage],
+    config: &Config,
+) -> Result<Vec<TilemapData>, ConversionError> {
+    ImageConverter::new(config.clone()).convert_batch(images)
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    /// A 64x64 image of varied colorful tiles
+    fn test_image() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, y| {
+            let tile = (y / 8) * 8 + x / 8;
+            Rgb([
+                (tile * 37 + x * 5) as u8,
+                (tile * 91 + y * 7) as u8,
+                (tile * 53 + (x ^ y) * 3) as u8,
+            ])
+        }))
     }
 
-    /// Write tile data to hex file (unique tiles only)
-    ///
-    /// Output format: 8 lines (one per tile row 0-7)
-    /// Each line: for each unique tile, write 2 chunks for that row
-    fn write_tiles_file(&self, unique_tiles: &[UniqueTile]) -> Result<(), ConversionError> {
-        let mut tile_data_file = File::create(&self.config.output_tiles_hex)?;
-
-        // For each row of the tile (0-7 for 8x8 tiles)
-        for row in 0..self.config.tile_height as usize {
-            // For each unique tile
-            for tile in unique_tiles.iter() {
-                let row_start = row * CHUNKS_PER_ROW;
-
-                // Write 2 chunks for this row of this tile
-                for chunk_offset in 0..CHUNKS_PER_ROW {
-                    let chunk_idx = row_start + chunk_offset;
-                    if chunk_idx < tile.quantized.len() {
-                        write!(&mut tile_data_file, "{:04x} ", tile.quantized[chunk_idx])?;
-                    } else {
-                        write!(&mut tile_data_file, "0000 ")?;
-                    }
-                }
-            }
-
-            // Pad remaining tiles if fewer than max_unique_tiles
-            for _ in unique_tiles.len()..self.config.max_unique_tiles {
-                write!(&mut tile_data_file, "0000 0000 ")?;
-            }
+    #[test]
+    fn tiles_are_shown_in_the_palette_they_were_dithered_in() {
+        let config = Config {
+            num_palettes: 4,
+            max_unique_tiles: 8,
+            ..Config::default()
+        };
+        let data = convert(&test_image(), &config).unwrap();
 
-            writeln!(&mut tile_data_file)?;
+        assert_eq!(data.tiles.len(), data.tilemap.len());
+        for (i, (tile, entry)) in data.tiles.iter().zip(&data.tilemap).enumerate() {
+            assert_eq!(tile.palette, entry.palette_index, "tile {i}");
         }
-
-        Ok(())
     }
 
-    /// Generate output image using unique tiles and tile assignments
-    fn generate_output_image_from_assignments(
-        &self,
-        unique_tiles: &[UniqueTile],
-        palettes: &[Palette],
-        tile_assignments: &[TileAssignment],
-    ) -> Result<RgbImage, ConversionError> {
-        let img_width = self.config.total_width();
-        let img_height = self.config.total_height();
-        let mut out_img = image::ImageBuffer::new(img_width, img_height);
-
-        for y in 0..self.config.tilemap_height {
-            for x in 0..self.config.tilemap_width {
-                let tilemap_idx = (y * self.config.tilemap_width + x) as usize;
-                let assignment = &tile_assignments[tilemap_idx];
-                let unique_tile = &unique_tiles[assignment.unique_tile_index];
-                let palette = &palettes[assignment.palette_index];
-
-                // Render this tile
-                for (chunk_idx, &chunk) in unique_tile.quantized.iter().enumerate() {
-                    let base_i = chunk_idx * PIXELS_PER_CHUNK;
-
-                    for pixel_offset in 0..PIXELS_PER_CHUNK {
-                        let pixel_idx = base_i + pixel_offset;
-                        if pixel_idx >= self.config.tile_size() {
-                            break;
-                        }
-
-                        let color_idx = ((chunk >> (pixel_offset * BITS_PER_COLOR)) & 0xF) as usize;
-                        if let Some(color) = palette.colors.get(color_idx) {
-                            let (r, g, b) = color.color.to_rgb();
-
-                            let pixel_y = pixel_idx / self.config.tile_width as usize;
-                            let pixel_x = pixel_idx % self.config.tile_width as usize;
+    #[test]
+    fn tilemap_entries_encode_palette_tile_and_flips() {
+        let entry = TilemapEntry::with_flip(3, 0x155, true, false);
+        assert_eq!(
+            entry.raw_value,
+            3 << PALETTE_INDEX_SHIFT | 0x155 | X_FLIP_BIT
+        );
 
-                            out_img.put_pixel(
-                                x * self.config.tile_width + pixel_x as u32,
-                                y * self.config.tile_height + pixel_y as u32,
-                                image::Rgb([r, g, b]),
-                            );
-                        }
-                    }
-                }
-            }
-        }
+        let entry = TilemapEntry::with_flip(15, 1, true, true);
+        assert_eq!(entry.raw_value, 0xfc01);
+    }
 
-        out_img.save(&self.config.output_png)?;
-        Ok(out_img)
+    #[test]
+    fn tile_flips_limit_the_palettes() {
+        assert_eq!(TileFlips::Off.variants(), &[(false, false)]);
+        assert_eq!(TileFlips::Horizontal.variants().len(), 2);
+        assert_eq!(TileFlips::Both.variants().len(), 4);
+        assert_eq!(TileFlips::Horizontal.max_palettes(), MAX_PALETTES);
+        assert_eq!(TileFlips::Both.max_palettes(), MAX_PALETTES / 2);
+        assert_eq!("both".parse::<TileFlips>(), Ok(TileFlips::Both));
+        assert!("diagonal".parse::<TileFlips>().is_err());
     }
 
-    /// Compare the output image with the original to calculate quality metrics
-    fn generate_error_metrics(
-        &self,
-        output_img: &RgbImage,
-        original_img: &image::DynamicImage,
-    ) -> Result<(), ConversionError> {
-        let mut delta_e_values =
-            Vec::with_capacity((self.config.total_width() * self.config.total_height()) as usize);
-
-        // Variables for PSNR calculation
-        let mut mse_r: f64 = 0.0;
-        let mut mse_g: f64 = 0.0;
-        let mut mse_b: f64 = 0.0;
-        let mut pixel_count: usize = 0;
-
-        // Calculate delta_e for each pixel and squared errors for PSNR
-        for (x, y, original_pixel) in original_img.pixels() {
-            if x >= output_img.width() || y >= output_img.height() {
-                continue;
-            }
+    #[test]
+    fn flipping_texels_twice_gives_them_back() {
+        let config = Config::default();
+        let tile: Vec<u16> = (0..config.chunks_per_tile() as u16)
+            .map(|i| i.wrapping_mul(0x1357))
+            .collect();
+        let flipped = flip_texels(&tile, &config, true, false);
+        assert_ne!(flipped, tile);
+        // The first texel of each row moves to the end of the row
+        assert_eq!(texel(&flipped, 7), texel(&tile, 0));
+        assert_eq!(flip_texels(&flipped, &config, true, false), tile);
+        assert_eq!(
+            flip_texels(
+                &flip_texels(&tile, &config, true, true),
+                &config,
+                true,
+                true
+            ),
+            tile
+        );
+    }
 
-            let original_rgb = original_pixel.to_rgb();
-            let output_pixel = output_img.get_pixel(x, y);
+    #[test]
+    fn mirrored_tiles_share_a_tile() {
+        // After a tile of its own come a pattern, the pattern mirrored
+        // horizontally and the pattern mirrored vertically. With the default
+        // error diffusion the mirrors still dither the same.
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(32, 8, |x, y| {
+            let (tile, x) = (x / 8, x % 8);
+            let (x, y) = match tile {
+                0 => return Rgb([255 - (x * 20) as u8, 40, (y * 20) as u8]),
+                2 => (7 - x, y),
+                3 => (x, 7 - y),
+                _ => (x, y),
+            };
+            Rgb([(x * 30) as u8, (y * 30) as u8, ((x + y) * 15) as u8])
+        }));
+        let config = Config {
+            num_palettes: 1,
+            tile_flips: TileFlips::Both,
+            ..Config::default()
+        };
+        let data = convert(&img, &config).unwrap();
 
-            // Calculate Delta E
-            let original_oklab = Oklab::from_rgb(original_rgb[0], original_rgb[1], original_rgb[2]);
-            let output_oklab = Oklab::from_rgb(output_pixel[0], output_pixel[1], output_pixel[2]);
-            let delta_e = oklab_delta_e(original_oklab, output_oklab) * DELTA_E_DISPLAY_FACTOR;
-            delta_e_values.push(delta_e);
+        assert_eq!(data.stats.distinct_tiles, 2);
+        let [_, pattern, x_mirror, y_mirror] = &data.tilemap[..] else {
+            panic!("expected 4 tilemap entries");
+        };
+        assert_eq!(pattern.tile_index, x_mirror.tile_index);
+        assert_eq!(pattern.tile_index, y_mirror.tile_index);
+        assert_ne!(pattern.x_flip, x_mirror.x_flip);
+        assert_eq!(pattern.y_flip, x_mirror.y_flip);
+        assert_ne!(pattern.y_flip, y_mirror.y_flip);
+        assert_eq!(pattern.x_flip, y_mirror.x_flip);
+    }
 
-            // Calculate squared error for each channel (for MSE/PSNR)
-            let diff_r = (original_rgb[0] as f64 - output_pixel[0] as f64).powi(2);
-            let diff_g = (original_rgb[1] as f64 - output_pixel[1] as f64).powi(2);
-            let diff_b = (original_rgb[2] as f64 - output_pixel[2] as f64).powi(2);
+    #[test]
+    fn conversions_return_their_stats() {
+        let config = Config {
+            num_palettes: 4,
+            max_unique_tiles: 16,
+            ..Config::default()
+        };
+        let data = convert(&test_image(), &config).unwrap();
+        let stats = &data.stats;
+        assert_eq!(stats.tiles, 64);
+        assert!(stats.distinct_tiles <= stats.tiles);
+        assert_eq!(stats.unique_tiles, data.unique_tiles.len());
+        assert!(stats.unique_tiles <= 16);
+        assert!(stats.min_palette_colors <= stats.max_palette_colors);
+        assert!(stats.redither_changes.len() <= config.redither_passes);
+    }
 
-            mse_r += diff_r;
-            mse_g += diff_g;
-            mse_b += diff_b;
-            pixel_count += 1;
+    #[test]
+    fn invalid_settings_are_rejected() {
+        assert!(Config::default().validate().is_ok());
+        let wide_tiles = Config {
+            tile_width: 16,
+            ..Config::default()
+        };
+        assert!(wide_tiles.validate().is_ok());
+        assert_eq!(wide_tiles.chunks_per_row(), 4);
+
+        let invalid = [
+            Config {
+                tile_width: 0,
+                ..Config::default()
+            },
+            Config {
+                tile_width: 3,
+                tile_height: 3,
+                ..Config::default()
+            },
+            Config {
+                num_palettes: MAX_PALETTES + 1,
+                ..Config::default()
+            },
+            Config {
+                tile_flips: TileFlips::Both,
+                ..Config::default()
+            },
+            Config {
+                colors_per_palette: MAX_COLORS_PER_PALETTE + 1,
+                ..Config::default()
+            },
+            Config {
+                max_unique_tiles: 0,
+                ..Config::default()
+            },
+            Config {
+                dither_factor: f32::NAN,
+                ..Config::default()
+            },
+            Config {
+                fill_color: "red".to_string(),
+                ..Config::default()
+            },
+            // 12 pixels is whole texel words, but a 6 pixel row isn't
+            Config {
+                tile_width: 6,
+                tile_height: 2,
+                ..Config::default()
+            },
+        ];
+        for config in invalid {
+            assert!(
+                matches!(config.validate(), Err(ConversionError::InvalidConfig(_))),
+                "{config:?}"
+            );
         }
+    }
 
-        // Sort the values to calculate percentiles
-        delta_e_values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
-
-        // Calculate statistics for Delta E
-        let min_delta_e = delta_e_values.first().copied().unwrap_or(0.0);
-        let max_delta_e = delta_e_values.last().copied().unwrap_or(0.0);
+    #[test]
+    fn configs_load_from_toml_and_json_with_defaults() {
+        let dir = std::env::temp_dir().join(format!("imgconv-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("config.toml"),
+            "num_palettes = 8\ndither_method = \"bayer\"\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("config.json"), r#"{"tile_flips": "horizontal"}"#).unwrap();
+
+        let toml = Config::load(dir.join("config.toml")).unwrap();
+        let json = Config::load(dir.join("config.json")).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(toml.num_palettes, 8);
+        assert_eq!(toml.dither_method, DitherMethod::Bayer);
+        assert_eq!(
+            toml.colors_per_palette,
+            Config::default().colors_per_palette
+        );
+        assert_eq!(json.tile_flips, TileFlips::Horizontal);
+    }
 
-        let sum_delta_e: f32 = delta_e_values.iter().sum();
-        let mean_delta_e = sum_delta_e / delta_e_values.len() as f32;
+    #[test]
+    fn images_are_fitted_to_whole_tiles_before_converting() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(20, 12, |x, y| {
+            Rgb([(x * 12) as u8, (y * 20) as u8, 64])
+        }));
+        let converter = ImageConverter::new(Config {
+            num_palettes: 2,
+            ..Config::default()
+        });
 
-        let median_index = delta_e_values.len() / 2;
-        let median_delta_e = delta_e_values.get(median_index).copied().unwrap_or(0.0);
+        let fitted = converter.fit(&img).unwrap();
+        assert_eq!(fitted.0.dimensions(), (24, 16));
+        assert_eq!(
+            (
+                fitted.1.config().tilemap_width,
+                fitted.1.config().tilemap_height
+            ),
+            (3, 2)
+        );
 
-        let p75_index = (delta_e_values.len() as f32 * 0.75) as usize;
-        let p75_delta_e = delta_e_values.get(p75_index).copied().unwrap_or(0.0);
+        let data = converter.convert_fitted(&[fitted]).unwrap().remove(0);
+        assert_eq!(data.tilemap.len(), 6);
+        assert_eq!(data.config.tilemap_width, 3);
+    }
 
-        let p90_index = (delta_e_values.len() as f32 * 0.90) as usize;
-        let p90_delta_e = delta_e_values.get(p90_index).copied().unwrap_or(0.0);
+    #[test]
+    fn transparent_pixels_use_the_reserved_color() {
+        // The left tile is transparent, the right one opaque with a
+        // transparent corner
+        let img = RgbaImage::from_fn(16, 8, |x, y| {
+            let alpha = if x < 8 || (x == 15 && y == 7) { 0 } else { 255 };
+            Rgba([(x * 16) as u8, (y * 32) as u8, 200, alpha])
+        });
+        let config = Config {
+            num_palettes: 1,
+            transparency: true,
+            ..Config::default()
+        };
+        let data = convert(&DynamicImage::ImageRgba8(img), &config).unwrap();
 
-        let p95_index = (delta_e_values.len() as f32 * 0.95) as usize;
-        let p95_delta_e = delta_e_values.get(p95_index).copied().unwrap_or(0.0);
+        assert!(data.palettes.iter().all(|palette| palette.transparent));
+        let tile = |x: usize| &data.unique_tiles[data.tilemap[x].tile_index].quantized;
+        assert!((0..64).all(|i| texel(tile(0), i) == TRANSPARENT_INDEX));
+        assert_eq!(texel(tile(1), 63), TRANSPARENT_INDEX);
+        assert!((0..63).all(|i| texel(tile(1), i) != TRANSPARENT_INDEX));
+    }
 
-        let p99_index = (delta_e_values.len() as f32 * 0.99) as usize;
-        let p99_delta_e = delta_e_values.get(p99_index).copied().unwrap_or(0.0);
+    #[test]
+    fn identical_tiles_are_merged_before_clustering() {
+        // Every tile but the first has the same pattern. With the default
+        // error diffusion the repeats still dither the same.
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(32, 16, |x, y| {
+            if x < 8 && y < 8 {
+                return Rgb([255 - (x * 20) as u8, 40, (y * 20) as u8]);
+            }
+            let (x, y) = (x % 8, y % 8);
+            Rgb([(x * 32) as u8, (y * 32) as u8, ((x ^ y) * 32) as u8])
+        }));
+        let config = Config {
+            num_palettes: 1,
+            ..Config::default()
+        };
+        let data = convert(&img, &config).unwrap();
 
-        // Calculate PSNR for each channel and average
-        let mse_r = mse_r / pixel_count as f64;
-        let mse_g = mse_g / pixel_count as f64;
-        let mse_b = mse_b / pixel_count as f64;
-        let mse_avg = (mse_r + mse_g + mse_b) / 3.0;
+        assert_eq!(data.stats.tiles, 8);
+        assert_eq!(data.stats.distinct_tiles, 2);
+        let repeated = data.tilemap[1].tile_index;
+        assert_ne!(data.tilemap[0].tile_index, repeated);
+        assert!(data.tilemap[1..]
+            .iter()
+            .all(|entry| entry.tile_index == repeated));
+    }
 
-        // PSNR = 20 * log10(MAX_PIXEL_VALUE) - 10 * log10(MSE)
-        let psnr_r = if mse_r > 0.0 {
-            20.0 * (MAX_PIXEL_VALUE as f64).log10() - 10.0 * mse_r.log10()
-        } else {
-            f64::INFINITY
-        };
-        let psnr_g = if mse_g > 0.0 {
-            20.0 * (MAX_PIXEL_VALUE as f64).log10() - 10.0 * mse_g.log10()
-        } else {
-            f64::INFINITY
-        };
-        let psnr_b = if mse_b > 0.0 {
-            20.0 * (MAX_PIXEL_VALUE as f64).log10() - 10.0 * mse_b.log10()
-        } else {
-            f64::INFINITY
-        };
-        let psnr_avg = if mse_avg > 0.0 {
-            20.0 * (MAX_PIXEL_VALUE as f64).log10() - 10.0 * mse_avg.log10()
-        } else {
-            f64::INFINITY
+    #[test]
+    fn batches_share_palettes_and_tiles() {
+        let first = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 8, |x, y| {
+            Rgb([(x * 16) as u8, (y * 32) as u8, 40])
+        }));
+        let second = DynamicImage::ImageRgb8(RgbImage::from_fn(8, 16, |x, y| {
+            Rgb([40, (x * 32) as u8, (y * 16) as u8])
+        }));
+        let config = Config {
+            num_palettes: 2,
+            ..Config::default()
         };
+        let data = convert_batch(&[first, second], &config).unwrap();
 
-        // Print formatted results
-        println!(
-            "\nImage Quality {}x Delta E Comparison (lower is better):",
-            DELTA_E_DISPLAY_FACTOR
+        assert_eq!(data.len(), 2);
+        assert_eq!(
+            (data[0].config.tilemap_width, data[0].config.tilemap_height),
+            (2, 1)
+        );
+        assert_eq!(
+            (data[1].config.tilemap_width, data[1].config.tilemap_height),
+            (1, 2)
         );
-        println!("  Min:    {:6.3}", min_delta_e);
-        println!("  Mean:   {:6.3}", mean_delta_e);
-        println!("  Median: {:6.3}", median_delta_e);
-        println!("  p75:    {:6.3}", p75_delta_e);
-        println!("  p90:    {:6.3}", p90_delta_e);
-        println!("  p95:    {:6.3}", p95_delta_e);
-        println!("  p99:    {:6.3}", p99_delta_e);
-        println!("  Max:    {:6.3}", max_delta_e);
-
-        println!("\nPSNR Quality Metrics (higher is better, 30.0-50.0 is good):");
-        println!("  Red channel:   {:6.3} dB", psnr_r);
-        println!("  Green channel: {:6.3} dB", psnr_g);
-        println!("  Blue channel:  {:6.3} dB", psnr_b);
-        println!("  Average PSNR:  {:6.3} dB", psnr_avg);
+        assert_eq!(data[0].stats.tiles, 4);
 
-        Ok(())
+        let words = |data: &TilemapData| {
+            (
+                crate::output::palette_words(data),
+                crate::output::tile_words(data),
+            )
+        };
+        assert_eq!(words(&data[0]), words(&data[1]));
+        for data in &data {
+            assert!(data
+                .tilemap
+                .iter()
+                .all(|entry| entry.tile_index < data.unique_tiles.len()));
+        }
     }
 
-    /// Create structured data for JSON output
-    fn create_tilemap_data(
-        &self,
-        raw_tiles: Vec<Vec<Oklab>>,
-        palettes: Vec<Palette>,
-        quantized_tiles: Vec<Vec<u16>>,
-        tilemap: Vec<u16>,
-    ) -> TilemapData {
-        // Create tiles with both raw and quantized data
-        let tiles: Vec<Tile> = raw_tiles
-            .into_iter()
-            .zip(quantized_tiles)
-            .map(|(pixels, quantized)| Tile { pixels, quantized })
-            .collect();
+    #[test]
+    fn locked_palettes_are_used_unchanged() {
+        let locked = [(0, 0, 0), (255, 0, 0), (255, 255, 255)];
+        let palette = Palette {
+            colors: locked
+                .iter()
+                .map(|&(r, g, b)| ColorFrequency::new(Oklab::from_rgb(r, g, b), 0))
+                .collect(),
+            transparent: false,
+        };
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 8, |x, y| {
+            if x < 8 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([40, (x * 16) as u8, (y * 32) as u8])
+            }
+        }));
+        let config = Config {
+            num_palettes: 2,
+            dithering: false,
+            locked_palettes: vec![palette],
+            ..Config::default()
+        };
+        let data = convert(&img, &config).unwrap();
 
-        // Create tilemap entries
-        let tilemap_entries: Vec<TilemapEntry> = tilemap
-            .into_iter()
-            .enumerate()
-            .map(|(i, raw_value)| TilemapEntry {
-                palette_index: ((raw_value >> PALETTE_INDEX_SHIFT) as usize)
-                    & (self.config.num_palettes - 1),
-                tile_index: i,
-                raw_value,
-            })
+        assert_eq!(data.palettes.len(), 2);
+        let colors: Vec<_> = data.palettes[0]
+            .colors
+            .iter()
+            .map(|color| color.color.to_rgb())
             .collect();
+        assert_eq!(colors, locked);
+        // the red tile needs no palette of its own
+        assert_eq!(data.tilemap[0].palette_index, 0);
+    }
 
-        TilemapData {
-            config: self.config.clone(),
-            tiles,
-            palettes,
-            tilemap: tilemap_entries,
-        }
+    #[test]
+    fn locked_colors_must_fit_the_color_depth() {
+        let config = |rgb: u32| Config {
+            color_depth: "444".parse().unwrap(),
+            locked_palettes: vec![Palette {
+                colors: vec![ColorFrequency::new(
+                    Oklab::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8),
+                    0,
+                )],
+                transparent: false,
+            }],
+            ..Config::default()
+        };
+        assert!(config(0x112233).validate().is_ok());
+        assert!(matches!(
+            config(0x123456).validate(),
+            Err(ConversionError::InvalidConfig(message)) if message.contains("123456")
+        ));
     }
 
-    /// Write JSON output file
-    fn write_json_file(&self, path: &str, data: &TilemapData) -> Result<(), ConversionError> {
-        let file = File::create(path)?;
-        serde_json::to_writer_pretty(file, data)?;
-        Ok(())
+    #[test]
+    fn ordered_dithering_keeps_repeated_tiles_identical() {
+        // The threshold map repeats every tile, so unlike error diffusion
+        // identical source tiles dither to identical tiles
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(32, 16, |x, y| {
+            let (x, y) = (x % 8, y % 8);
+            Rgb([(x * 32) as u8, (y * 32) as u8, ((x ^ y) * 32) as u8])
+        }));
+        let config = Config {
+            num_palettes: 1,
+            dither_method: DitherMethod::Bayer,
+            ..Config::default()
+        };
+        let data = convert(&img, &config).unwrap();
+
+        assert_eq!(data.unique_tiles.len(), 1);
+        assert!(data.tilemap.iter().all(|entry| entry.tile_index == 0));
     }
 }
@@ -0,0 +1,346 @@
+//! Inspection of hex asset files that have already been written
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+use image::{Rgb, RgbImage};
+
+use crate::hexfile;
+use crate::imgconv::{
+    flipped_pixel, texel, Config, ConversionError, TileFlips, TilemapEntry, PALETTE_INDEX_SHIFT,
+    X_FLIP_BIT, Y_FLIP_BIT,
+};
+
+/// Mask for the tile index in a tilemap entry
+const TILE_INDEX_MASK: u32 = 0x3ff;
+/// Number of tiles in each row of the tile sheet
+const TILE_SHEET_COLUMNS: usize = 32;
+/// Size in pixels of each color in the palette swatch
+const SWATCH_SIZE: u32 = 16;
+
+/// The contents of a palette, tiles and tilemap hex file set
+#[derive(Debug, Clone, Default)]
+pub struct HexAssets {
+    /// 24 bit `rrggbb` colours
+    pub palette: Vec<u32>,
+    /// Texel words, row-interleaved like tiles.hex
+    pub tiles: Vec<u32>,
+    /// `palette << 10 | tile` entries
+    pub tilemap: Vec<u32>,
+}
+
+impl HexAssets {
+    /// Read the three hex files
+    pub fn load(
+        palette_hex: impl AsRef<Path>,
+        tiles_hex: impl AsRef<Path>,
+        tilemap_hex: impl AsRef<Path>,
+    ) -> Result<Self, ConversionError> {
+        Ok(HexAssets {
+            palette: hexfile::read(palette_hex)?,
+            tiles: hexfile::read(tiles_hex)?,
+            tilemap: hexfile::read(tilemap_hex)?,
+        })
+    }
+
+    /// Number of words between consecutive rows of the same tile
+    fn tile_row_stride(config: &Config) -> usize {
+        config.max_unique_tiles * config.chunks_per_row()
+    }
+
+    /// Whether any texel of the tile in the given slot is non-zero
+    fn tile_is_used(&self, config: &Config, tile: usize) -> bool {
+        let chunks_per_row = config.chunks_per_row();
+        (0..config.tile_height as usize).any(|row| {
+            let start = row * Self::tile_row_stride(config) + tile * chunks_per_row;
+            (start..start + chunks_per_row)
+                .any(|address| self.tiles.get(address).is_some_and(|&word| word != 0))
+        })
+    }
+
+    /// Gather the texel words of the tile in the given slot, in the order of
+    /// [`crate::imgconv::UniqueTile::quantized`]
+    fn tile_texels(&self, config: &Config, tile: usize) -> Vec<u16> {
+        let chunks_per_row = config.chunks_per_row();
+        (0..config.tile_height as usize)
+            .flat_map(|row| {
+                let start = row * Self::tile_row_stride(config) + tile * chunks_per_row;
+                start..start + chunks_per_row
+            })
+            .map(|address| self.tiles.get(address).copied().unwrap_or(0) as u16)
+            .collect()
+    }
+
+    /// Decode a tilemap entry, flips being the bits the configuration says
+    /// the entries encode
+    fn entry(config: &Config, raw: u32) -> TilemapEntry {
+        let raw = raw as u16;
+        let x_flip = config.tile_flips != TileFlips::Off && raw & X_FLIP_BIT != 0;
+        let y_flip = config.tile_flips == TileFlips::Both && raw & Y_FLIP_BIT != 0;
+        let palette_index_mask = config.tile_flips.max_palettes() - 1;
+        TilemapEntry::with_flip(
+            (raw as usize >> PALETTE_INDEX_SHIFT) & palette_index_mask,
+            (raw as u32 & TILE_INDEX_MASK) as usize,
+            x_flip,
+            y_flip,
+        )
+    }
+
+    /// The color at an index of a palette, black past the end of the file
+    fn color(&self, config: &Config, palette: usize, index: usize) -> Rgb<u8> {
+        let word = self
+            .palette
+            .get(palette * config.colors_per_palette + index)
+            .copied()
+            .unwrap_or(0);
+        Rgb([(word >> 16) as u8, (word >> 8) as u8, word as u8])
+    }
+
+    /// Draw a tile with its top left corner at the given pixel
+    fn draw_tile(&self, img: &mut RgbImage, config: &Config, entry: &TilemapEntry, x: u32, y: u32) {
+        let texels = self.tile_texels(config, entry.tile_index);
+        for pixel_idx in 0..config.tile_size() {
+            let source_idx = flipped_pixel(config, pixel_idx, entry.x_flip, entry.y_flip);
+            let color = self.color(config, entry.palette_index, texel(&texels, source_idx));
+            img.put_pixel(
+                x + (pixel_idx % config.tile_width as usize) as u32,
+                y + (pixel_idx / config.tile_width as usize) as u32,
+                color,
+            );
+        }
+    }
+
+    /// Render the tilemap the way the hardware will display it
+    ///
+    /// Entries selecting tiles past the tile slots are left black.
+    pub fn render_image(&self, config: &Config) -> RgbImage {
+        let width = config.tilemap_width as usize;
+        let height = self.tilemap.len().div_ceil(width);
+        let mut img = RgbImage::new(
+            width as u32 * config.tile_width,
+            height as u32 * config.tile_height,
+        );
+
+        for (i, &raw) in self.tilemap.iter().enumerate() {
+            let entry = Self::entry(config, raw);
+            if entry.tile_index >= config.max_unique_tiles {
+                continue;
+            }
+            let x = (i % width) as u32 * config.tile_width;
+            let y = (i / width) as u32 * config.tile_height;
+            self.draw_tile(&mut img, config, &entry, x, y);
+        }
+
+        img
+    }
+
+    /// Render every tile slot in order, a row of 32 at a time
+    ///
+    /// Tiles are shown in the palette of the first tilemap entry that uses
+    /// them, or in palette 0 if none does.
+    pub fn render_tile_sheet(&self, config: &Config) -> RgbImage {
+        let mut palettes = vec![None; config.max_unique_tiles];
+        for &raw in &self.tilemap {
+            let entry = Self::entry(config, raw);
+            if let Some(palette @ None) = palettes.get_mut(entry.tile_index) {
+                *palette = Some(entry.palette_index);
+            }
+        }
+
+        let columns = TILE_SHEET_COLUMNS.min(config.max_unique_tiles);
+        let rows = config.max_unique_tiles.div_ceil(columns);
+        let mut img = RgbImage::new(
+            columns as u32 * config.tile_width,
+            rows as u32 * config.tile_height,
+        );
+
+        for (tile, palette) in palettes.into_iter().enumerate() {
+            let entry = TilemapEntry::new(palette.unwrap_or(0), tile);
+            let x = (tile % columns) as u32 * config.tile_width;
+            let y = (tile / columns) as u32 * config.tile_height;
+            self.draw_tile(&mut img, config, &entry, x, y);
+        }
+
+        img
+    }
+
+    /// Render the palettes as a row of squares each
+    pub fn render_palette_swatch(&self, config: &Config) -> RgbImage {
+        let colors = config.colors_per_palette;
+        let palettes = self.palette.len().div_ceil(colors).max(1);
+        let mut img = RgbImage::new(colors as u32 * SWATCH_SIZE, palettes as u32 * SWATCH_SIZE);
+
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let palette = (y / SWATCH_SIZE) as usize;
+            let index = (x / SWATCH_SIZE) as usize;
+            *pixel = self.color(config, palette, index);
+        }
+
+        img
+    }
+
+    /// Summarise the assets, interpreting them with the given configuration
+    pub fn summary(&self, config: &Config) -> HexSummary {
+        let palettes = self.palette.len().div_ceil(config.colors_per_palette);
+        let distinct_colors: BTreeSet<u32> = self.palette.iter().copied().collect();
+        let used_tile_slots = (0..config.max_unique_tiles)
+            .filter(|&tile| self.tile_is_used(config, tile))
+            .count();
+
+        let tile_indices: BTreeSet<u32> = self
+            .tilemap
+            .iter()
+            .map(|entry| entry & TILE_INDEX_MASK)
+            .collect();
+        // Bits above the palette index are flips
+        let palette_index_mask = config.tile_flips.max_palettes() as u32 - 1;
+        let palette_indices: BTreeSet<u32> = self
+            .tilemap
+            .iter()
+            .map(|entry| (entry >> PALETTE_INDEX_SHIFT) & palette_index_mask)
+            .collect();
+
+        HexSummary {
+            palettes,
+            distinct_colors: distinct_colors.len(),
+            tile_slots: config.max_unique_tiles,
+            used_tile_slots,
+            tilemap_entries: self.tilemap.len(),
+            tilemap_width: config.tilemap_width as usize,
+            tiles_referenced: tile_indices.len(),
+            tiles_out_of_range: tile_indices
+                .iter()
+                .filter(|&&tile| tile as usize >= config.max_unique_tiles)
+                .count(),
+            palettes_referenced: palette_indices.len(),
+            palettes_out_of_range: palette_indices
+                .iter()
+                .filter(|&&palette| palette as usize >= palettes)
+                .count(),
+        }
+    }
+}
+
+/// Counts describing a set of hex assets
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HexSummary {
+    pub palettes: usize,
+    pub distinct_colors: usize,
+    pub tile_slots: usize,
+    pub used_tile_slots: usize,
+    pub tilemap_entries: usize,
+    pub tilemap_width: usize,
+    pub tiles_referenced: usize,
+    pub tiles_out_of_range: usize,
+    pub palettes_referenced: usize,
+    pub palettes_out_of_range: usize,
+}
+
+impl fmt::Display for HexSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Palettes: {} ({} distinct colors)",
+            self.palettes, self.distinct_colors
+        )?;
+        writeln!(
+            f,
+            "Tiles: {} of {} slots have texels",
+            self.used_tile_slots, self.tile_slots
+        )?;
+        write!(
+            f,
+            "Tilemap: {} entries ({}x{}), using {} tiles and {} palettes",
+            self.tilemap_entries,
+            self.tilemap_width,
+            self.tilemap_entries.div_ceil(self.tilemap_width),
+            self.tiles_referenced,
+            self.palettes_referenced
+        )?;
+        if self.tiles_out_of_range > 0 {
+            write!(
+                f,
+                "\nWarning: {} tile indices are past the {} tile slots",
+                self.tiles_out_of_range, self.tile_slots
+            )?;
+        }
+        if self.palettes_out_of_range > 0 {
+            write!(
+                f,
+                "\nWarning: {} palette indices are past the {} palettes",
+                self.palettes_out_of_range, self.palettes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::DynamicImage;
+
+    use super::*;
+    use crate::imgconv::convert;
+    use crate::output;
+
+    #[test]
+    fn written_hex_files_render_like_the_conversion() {
+        // Three tiles, the last mirroring the first
+        let img = RgbImage::from_fn(24, 8, |x, y| {
+            let x = if x >= 16 { 23 - x } else { x };
+            Rgb([(x * 10) as u8, (y * 30) as u8, (x * y) as u8])
+        });
+        let config = Config {
+            num_palettes: 2,
+            max_unique_tiles: 4,
+            tile_flips: TileFlips::Horizontal,
+            ..Config::default()
+        };
+        let data = convert(&DynamicImage::ImageRgb8(img), &config).unwrap();
+        let config = &data.config;
+
+        let dir = std::env::temp_dir().join(format!("imgconv-inspect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        output::write_palette_hex(&data, dir.join("palette.hex")).unwrap();
+        output::write_tiles_hex(&data, dir.join("tiles.hex")).unwrap();
+        output::write_tilemap_hex(&data, dir.join("tile_map.hex")).unwrap();
+        let assets = HexAssets::load(
+            dir.join("palette.hex"),
+            dir.join("tiles.hex"),
+            dir.join("tile_map.hex"),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(assets.render_image(config), output::render_image(&data));
+
+        let summary = assets.summary(config);
+        assert_eq!(summary.palettes, 2);
+        assert_eq!(summary.tile_slots, 4);
+        // A tile of color 0 alone has no texels set
+        assert!(summary.used_tile_slots <= data.unique_tiles.len());
+        assert_eq!(summary.tilemap_entries, 3);
+        let referenced: BTreeSet<usize> = data.tilemap.iter().map(|e| e.tile_index).collect();
+        assert_eq!(summary.tiles_referenced, referenced.len());
+        assert_eq!(summary.tiles_out_of_range, 0);
+    }
+
+    #[test]
+    fn flip_bits_are_only_read_when_configured() {
+        let raw = (X_FLIP_BIT | Y_FLIP_BIT) as u32 | 3 << PALETTE_INDEX_SHIFT | 5;
+        let config = Config::default();
+        let entry = HexAssets::entry(&config, raw);
+        // Without flips bit 14 is the top bit of a 5 bit palette index
+        assert_eq!((entry.palette_index, entry.tile_index), (0x13, 5));
+        assert!(!entry.x_flip && !entry.y_flip);
+
+        let config = Config {
+            tile_flips: TileFlips::Both,
+            ..Config::default()
+        };
+        let entry = HexAssets::entry(&config, raw);
+        assert_eq!((entry.palette_index, entry.tile_index), (3, 5));
+        assert!(entry.x_flip && entry.y_flip);
+    }
+}
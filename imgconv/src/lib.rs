@@ -0,0 +1,23 @@
+//! Converts images into the palettes, tiles and tilemaps the VDP displays
+//!
+//! [`convert`] does the conversion in memory, and the [`output`] module
+//! writes the result out in the formats the RTL and emulator load.
+
+#![feature(portable_simd)]
+
+pub mod binfile;
+pub mod color;
+pub mod dither;
+pub mod fit;
+pub mod hexfile;
+pub mod imgconv;
+pub mod inspect;
+pub mod metrics;
+pub mod output;
+pub mod source;
+pub mod sprite;
+
+pub use imgconv::{
+    convert, convert_batch, Config, ConversionError, ConversionStats, ImageConverter, Palette,
+    Tile, TileFlips, TilemapData, TilemapEntry, UniqueTile,
+};
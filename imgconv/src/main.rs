@@ -3,185 +3,638 @@
 //
 // Gouldian_Finch_256x256.png is public domain photo by Bernard Spragg
 
-#![feature(portable_simd)]
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-mod color;
-mod imgconv;
-use imgconv::{Config, ImageConverter};
+use clap::{Args, Parser, Subcommand};
 
-/// Command line interface to make it easier to use different configurations
-fn main() -> Result<(), crate::imgconv::ConversionError> {
-    let args: Vec<String> = std::env::args().collect();
-
-    // If no arguments provided, run with default config
-    if args.len() <= 1 {
-        let config = Config::default();
-        println!("Running with default configuration:");
-        println!("Input: {}", config.input_file);
-        println!("Output PNG: {}", config.output_png);
-        println!("Tile size: {}x{}", config.tile_width, config.tile_height);
-        println!(
-            "Tilemap size: {}x{}",
-            config.tilemap_width, config.tilemap_height
-        );
-        println!("Palettes: {}", config.num_palettes);
-        println!("Colors per palette: {}", config.colors_per_palette);
-        println!("Dithering: {}", if config.dithering { "on" } else { "off" });
-
-        let converter = ImageConverter::new(config);
-        converter.convert()?;
-        return Ok(());
-    }
-
-    // Parse arguments and create custom config
-    let mut config = Config::default();
-    let mut i = 1;
-
-    while i < args.len() {
-        match args[i].as_str() {
-            "-i" | "--input" => {
-                i += 1;
-                if i < args.len() {
-                    config.input_file = args[i].clone();
-                }
-            }
-            "-o" | "--output" => {
-                i += 1;
-                if i < args.len() {
-                    config.output_png = args[i].clone();
-                }
-            }
-            "--palette-hex" => {
-                i += 1;
-                if i < args.len() {
-                    config.output_palette_hex = args[i].clone();
-                }
-            }
-            "--tiles-hex" => {
-                i += 1;
-                if i < args.len() {
-                    config.output_tiles_hex = args[i].clone();
-                }
-            }
-            "--tilemap-hex" => {
-                i += 1;
-                if i < args.len() {
-                    config.output_tilemap_hex = args[i].clone();
-                }
-            }
-            "--json" => {
-                i += 1;
-                if i < args.len() {
-                    config.output_json = Some(args[i].clone());
-                }
-            }
-            "--tile-size" => {
-                i += 1;
-                if i < args.len() {
-                    let parts: Vec<&str> = args[i].split('x').collect();
-                    if parts.len() == 2 {
-                        if let Ok(width) = parts[0].parse::<u32>() {
-                            config.tile_width = width;
-                        }
-                        if let Ok(height) = parts[1].parse::<u32>() {
-                            config.tile_height = height;
-                        }
-                    }
-                }
-            }
-            "--tilemap-size" => {
-                i += 1;
-                if i < args.len() {
-                    let parts: Vec<&str> = args[i].split('x').collect();
-                    if parts.len() == 2 {
-                        if let Ok(width) = parts[0].parse::<u32>() {
-                            config.tilemap_width = width;
-                        }
-                        if let Ok(height) = parts[1].parse::<u32>() {
-                            config.tilemap_height = height;
-                        }
-                    }
-                }
-            }
-            "--palettes" => {
-                i += 1;
-                if i < args.len() {
-                    if let Ok(num) = args[i].parse::<usize>() {
-                        config.num_palettes = num;
-                    }
-                }
-            }
-            "--colors" => {
-                i += 1;
-                if i < args.len() {
-                    if let Ok(num) = args[i].parse::<usize>() {
-                        config.colors_per_palette = num.min(16);
-                    }
-                }
-            }
-            "--no-dither" => {
-                config.dithering = false;
-            }
-            "--dither-factor" => {
-                i += 1;
-                if i < args.len() {
-                    if let Ok(factor) = args[i].parse::<f32>() {
-                        config.dither_factor = factor;
-                    }
-                }
-            }
-            "--help" => {
-                println!("Image Converter - Converts images to tilemap format");
-                println!();
-                println!("Usage:");
-                println!("  imgconv [options]");
-                println!();
-                println!("Options:");
-                println!("  -i, --input FILE         Input image file (default: imgconv/Gouldian_Finch_256x256.png)");
-                println!("  -o, --output FILE        Output PNG file (default: imgconv/out.png)");
-                println!(
-                    "  --palette-hex FILE       Output palette hex file (default: rtl/palette.hex)"
-                );
-                println!(
-                    "  --tiles-hex FILE         Output tiles hex file (default: rtl/tiles.hex)"
-                );
-                println!("  --tilemap-hex FILE       Output tilemap hex file (default: rtl/tile_map.hex)");
-                println!("  --json FILE              Output JSON file (optional)");
-                println!("  --tile-size WIDTHxHEIGHT Tile size in pixels (default: 8x8)");
-                println!(
-                    "  --tilemap-size WIDTHxHEIGHT Tilemap dimensions in tiles (default: 32x32)"
-                );
-                println!("  --palettes NUM           Number of palettes to generate (default: 32)");
-                println!("  --colors NUM             Max colors per palette, 1-16 (default: 16)");
-                println!("  --no-dither              Disable dithering");
-                println!(
-                    "  --dither-factor FLOAT    Error scaling factor for dithering (default: 0.75)"
-                );
-                println!("  --help                   Show this help message");
-                return Ok(());
-            }
-            _ => {
-                println!("Unknown option: {}", args[i]);
-                println!("Use --help for usage information.");
-                return Ok(());
-            }
-        }
-        i += 1;
-    }
-
-    println!("Running with custom configuration:");
+use imgconv::color::ColorDepth;
+use imgconv::dither::DitherMethod;
+use imgconv::fit::{Anchor, FitMode};
+use imgconv::inspect::HexAssets;
+use imgconv::metrics::QualityReport;
+use imgconv::output::{self, MaskGranularity};
+use imgconv::source;
+use imgconv::sprite::{self, SpriteLayout};
+use imgconv::{
+    Config, ConversionError, ConversionStats, ImageConverter, Palette, TileFlips, TilemapData,
+};
+
+/// Image Converter - Converts images to tilemap format
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// What to do, `convert` with the options below if not given
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    convert: ConvertArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert an image and write the hex files, the preview PNG and optionally JSON
+    Convert(ConvertArgs),
+    /// Convert an image but only write the preview PNG
+    Preview(ConvertArgs),
+    /// Convert several images into shared palette and tiles hex files, with a
+    /// tilemap hex file and preview PNG for each
+    Batch(BatchArgs),
+    /// Convert a sprite sheet and write sprite records for the sprites in a layout
+    Sprites(SpritesArgs),
+    /// Summarise hex files that have already been written and render them to PNGs
+    Inspect(InspectArgs),
+}
+
+/// Converting a single image
+#[derive(Args, Default)]
+struct ConvertArgs {
+    /// Input image file [default: imgconv/Gouldian_Finch_256x256.png]
+    #[arg(short, long, value_name = "FILE")]
+    input: Option<String>,
+    /// Output PNG file [default: imgconv/out.png]
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<String>,
+    /// Output tilemap hex file [default: rtl/tile_map.hex]
+    #[arg(long, value_name = "FILE")]
+    tilemap_hex: Option<String>,
+    /// Output JSON file
+    #[arg(long, value_name = "FILE")]
+    json: Option<String>,
+    /// Output binary asset file, with a header then the palette, tiles,
+    /// tilemap and mask as little-endian words
+    #[arg(long, value_name = "FILE")]
+    bin: Option<String>,
+    /// Directory to write headerless little-endian palette.bin, tiles.bin,
+    /// tile_map.bin and (with transparency) mask.bin to
+    #[arg(long, value_name = "DIR")]
+    raw_dir: Option<String>,
+    /// Output Rust module with the assets as const arrays
+    #[arg(long, value_name = "FILE")]
+    rust: Option<String>,
+    /// Output C header with the assets as const arrays, named after the file
+    #[arg(long, value_name = "FILE")]
+    c_header: Option<String>,
+    /// Output JSON quality report: Delta E, PSNR, SSIM, error of each tile
+    /// and palette usage
+    #[arg(long, value_name = "FILE")]
+    report: Option<String>,
+    /// Grayscale image the size of the input, not dithered where it's black
+    #[arg(long, value_name = "FILE")]
+    dither_mask: Option<String>,
+    #[command(flatten)]
+    settings: SettingsArgs,
+}
+
+impl ConvertArgs {
+    /// Build the configuration: defaults, then the config file, then options
+    fn config(&self) -> Result<Config, ConversionError> {
+        let mut config = self.settings.config()?;
+
+        if let Some(input) = &self.input {
+            config.input_file = input.clone();
+        }
+        if let Some(output) = &self.output {
+            config.output_png = output.clone();
+        }
+        if let Some(tilemap_hex) = &self.tilemap_hex {
+            config.output_tilemap_hex = tilemap_hex.clone();
+        }
+        if let Some(json) = &self.json {
+            config.output_json = Some(json.clone());
+        }
+        if let Some(bin) = &self.bin {
+            config.output_bin = Some(bin.clone());
+        }
+        if let Some(raw_dir) = &self.raw_dir {
+            config.output_raw_dir = Some(raw_dir.clone());
+        }
+        if let Some(rust) = &self.rust {
+            config.output_rust = Some(rust.clone());
+        }
+        if let Some(c_header) = &self.c_header {
+            config.output_c_header = Some(c_header.clone());
+        }
+        if let Some(report) = &self.report {
+            config.output_report = Some(report.clone());
+        }
+        if let Some(dither_mask) = &self.dither_mask {
+            config.dither_mask = Some(dither_mask.clone());
+        }
+
+        // Only file names are set here, the settings were validated above
+        Ok(config)
+    }
+}
+
+/// Converting several images together
+#[derive(Args)]
+struct BatchArgs {
+    /// Input image files
+    #[arg(required = true, value_name = "IMAGE")]
+    images: Vec<PathBuf>,
+    /// Directory to write each image's `<name>.hex` tilemap and `<name>.png` preview to
+    #[arg(long, value_name = "DIR", default_value = "imgconv/batch")]
+    out_dir: PathBuf,
+    /// Also write each image's binary asset file, `<name>.bin`, with the
+    /// shared palette and tiles and its own tilemap
+    #[arg(long)]
+    bin: bool,
+    /// Also write each image's JSON quality report, `<name>.report.json`
+    #[arg(long)]
+    report: bool,
+    #[command(flatten)]
+    settings: SettingsArgs,
+}
+
+/// Converting a sprite sheet and laying out sprites from it
+#[derive(Args)]
+struct SpritesArgs {
+    /// Sprite sheet image
+    #[arg(value_name = "IMAGE")]
+    sheet: PathBuf,
+    /// JSON or TOML file naming frames of the sheet (rectangles in tiles)
+    /// and placing sprites showing them on screen
+    #[arg(long, value_name = "FILE")]
+    layout: PathBuf,
+    /// Output sprites hex file, a 144 bit sprite BRAM record per line
+    #[arg(long, value_name = "FILE", default_value = "rtl/sprites.hex")]
+    sprites_hex: PathBuf,
+    /// Output tilemap hex file
+    #[arg(long, value_name = "FILE", default_value = "rtl/tile_map.hex")]
+    tilemap_hex: PathBuf,
+    /// Output PNG of the converted sheet
+    #[arg(short, long, value_name = "FILE", default_value = "imgconv/sheet.png")]
+    output: PathBuf,
+    #[command(flatten)]
+    settings: SettingsArgs,
+}
+
+/// Conversion settings. Anything not given comes from the config file, or
+/// the defaults if there isn't one.
+#[derive(Args, Default)]
+struct SettingsArgs {
+    /// JSON or TOML file to load the configuration from
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Output palette hex file [default: rtl/palette.hex]
+    #[arg(long, value_name = "FILE")]
+    palette_hex: Option<String>,
+    /// Output tiles hex file [default: rtl/tiles.hex]
+    #[arg(long, value_name = "FILE")]
+    tiles_hex: Option<String>,
+    /// Output transparency mask hex file
+    #[arg(long, value_name = "FILE")]
+    mask_hex: Option<String>,
+    /// Mask bits per texel: pixel (one) or sub-pixel (one per doubled pixel) [default: pixel]
+    #[arg(long, value_name = "GRANULARITY")]
+    mask_granularity: Option<MaskGranularity>,
+    /// Palette hex file (one palette per line) or JSON array of palettes to use
+    /// unchanged as the first palettes
+    #[arg(long, value_name = "FILE")]
+    locked_palettes: Option<PathBuf>,
+    /// Tile size in pixels, the width a multiple of 4 [default: 8x8]
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    tile_size: Option<(u32, u32)>,
+    /// Tilemap dimensions in tiles, when not derived from the image [default: 32x32]
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    tilemap_size: Option<(u32, u32)>,
+    /// How to fit the image: exact, derive (size the tilemap to the image),
+    /// pad (pad or crop to the tilemap size) or scale (scale to fit, then pad)
+    /// [default: derive]
+    #[arg(long, value_name = "MODE")]
+    fit: Option<FitMode>,
+    /// Where to place a padded or cropped image: top-left, top, top-right,
+    /// left, center, right, bottom-left, bottom or bottom-right [default: top-left]
+    #[arg(long, value_name = "ANCHOR")]
+    anchor: Option<Anchor>,
+    /// Padding colour [default: 000000]
+    #[arg(long, value_name = "RRGGBB")]
+    fill_color: Option<String>,
+    /// Number of palettes to generate, 1-32 [default: 32]
+    #[arg(long, value_name = "NUM")]
+    palettes: Option<usize>,
+    /// Max colors per palette, 1-16 [default: 16]
+    #[arg(long, value_name = "NUM")]
+    colors: Option<usize>,
+    /// Maximum number of unique tiles, 1-1024 [default: 256]
+    #[arg(long, value_name = "NUM")]
+    max_tiles: Option<usize>,
+    /// Let mirrored tiles share a tile, flipped by tilemap entry bit 15
+    /// (horizontal) and bit 14 (both, at most 16 palettes): off, horizontal
+    /// or both. Mirrors are dithered once, so they share with any dithering
+    /// [default: off]
+    #[arg(long, value_name = "FLIPS")]
+    tile_flips: Option<TileFlips>,
+    /// Bits per channel of the palette RAM, like 444, 555 or 565 [default: 888]
+    #[arg(long, value_name = "RGB")]
+    color_depth: Option<ColorDepth>,
+    /// Disable dithering
+    #[arg(long)]
+    no_dither: bool,
+    /// Error scaling factor for dithering [default: 0.75]
+    #[arg(long, value_name = "FLOAT")]
+    dither_factor: Option<f32>,
+    /// Dithering to use: sierra, floyd-steinberg, atkinson, sierra-lite,
+    /// bayer or blue-noise [default: sierra]
+    #[arg(long, value_name = "METHOD")]
+    dither: Option<DitherMethod>,
+    /// Times to dither again in the palettes the tiles were finally given,
+    /// 0 to keep the first dither [default: 2]
+    #[arg(long, value_name = "NUM")]
+    redither_passes: Option<usize>,
+    /// Reserve color 0 of every palette for transparent pixels
+    #[arg(long)]
+    transparency: bool,
+    /// Pixels with alpha below this are transparent [default: 128]
+    #[arg(long, value_name = "ALPHA")]
+    alpha_threshold: Option<u8>,
+}
+
+impl SettingsArgs {
+    /// Build the configuration: defaults, then the config file, then options,
+    /// and check it is valid
+    fn config(&self) -> Result<Config, ConversionError> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        if let Some(palette_hex) = &self.palette_hex {
+            config.output_palette_hex = palette_hex.clone();
+        }
+        if let Some(tiles_hex) = &self.tiles_hex {
+            config.output_tiles_hex = tiles_hex.clone();
+        }
+        if let Some(mask_hex) = &self.mask_hex {
+            config.output_mask_hex = Some(mask_hex.clone());
+        }
+        if let Some(mask_granularity) = self.mask_granularity {
+            config.mask_granularity = mask_granularity;
+        }
+        if let Some((width, height)) = self.tile_size {
+            config.tile_width = width;
+            config.tile_height = height;
+        }
+        if let Some((width, height)) = self.tilemap_size {
+            config.tilemap_width = width;
+            config.tilemap_height = height;
+        }
+        if let Some(fit) = self.fit {
+            config.fit = fit;
+        }
+        if let Some(anchor) = self.anchor {
+            config.anchor = anchor;
+        }
+        if let Some(fill_color) = &self.fill_color {
+            config.fill_color = fill_color.clone();
+        }
+        if let Some(palettes) = self.palettes {
+            config.num_palettes = palettes;
+        }
+        if let Some(colors) = self.colors {
+            config.colors_per_palette = colors;
+        }
+        if let Some(max_tiles) = self.max_tiles {
+            config.max_unique_tiles = max_tiles;
+        }
+        if let Some(tile_flips) = self.tile_flips {
+            config.tile_flips = tile_flips;
+        }
+        if let Some(color_depth) = self.color_depth {
+            config.color_depth = color_depth;
+        }
+        if self.no_dither {
+            config.dithering = false;
+        }
+        if let Some(dither_factor) = self.dither_factor {
+            config.dither_factor = dither_factor;
+        }
+        if let Some(dither_method) = self.dither {
+            config.dither_method = dither_method;
+        }
+        if let Some(redither_passes) = self.redither_passes {
+            config.redither_passes = redither_passes;
+        }
+        if self.transparency {
+            config.transparency = true;
+        }
+        if let Some(alpha_threshold) = self.alpha_threshold {
+            config.alpha_threshold = alpha_threshold;
+        }
+        if let Some(path) = &self.locked_palettes {
+            config.locked_palettes = Palette::load(path, config.transparency)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// JSON or TOML file with the tilemap width and tile layout the files were written with
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Palette hex file
+    #[arg(long, value_name = "FILE", default_value = "rtl/palette.hex")]
+    palette_hex: PathBuf,
+    /// Tiles hex file
+    #[arg(long, value_name = "FILE", default_value = "rtl/tiles.hex")]
+    tiles_hex: PathBuf,
+    /// Tilemap hex file
+    #[arg(long, value_name = "FILE", default_value = "rtl/tile_map.hex")]
+    tilemap_hex: PathBuf,
+    /// Output PNG of the image the files display
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Output PNG of every tile slot
+    #[arg(long, value_name = "FILE")]
+    tile_sheet: Option<PathBuf>,
+    /// Output PNG of the palettes, a row of colors each
+    #[arg(long, value_name = "FILE")]
+    swatch: Option<PathBuf>,
+}
+
+/// Parse a `WIDTHxHEIGHT` size
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("{size} is not in WIDTHxHEIGHT form"))?;
+    let parse = |value: &str| {
+        value
+            .parse::<u32>()
+            .map_err(|e| format!("{value} in {size}: {e}"))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+/// Print the settings a conversion will use
+fn print_config(config: &Config) {
     println!("Input: {}", config.input_file);
     println!("Output PNG: {}", config.output_png);
     println!("Tile size: {}x{}", config.tile_width, config.tile_height);
     println!(
-        "Tilemap size: {}x{}",
-        config.tilemap_width, config.tilemap_height
+        "Tilemap size: {}x{} ({})",
+        config.tilemap_width, config.tilemap_height, config.fit
     );
     println!("Palettes: {}", config.num_palettes);
+    if !config.locked_palettes.is_empty() {
+        println!("Locked palettes: {}", config.locked_palettes.len());
+    }
     println!("Colors per palette: {}", config.colors_per_palette);
-    println!("Dithering: {}", if config.dithering { "on" } else { "off" });
+    if !config.color_depth.is_full() {
+        println!("Color depth: RGB{}", config.color_depth);
+    }
+    if config.dithering {
+        println!("Dithering: {}", config.dither_method);
+    } else {
+        println!("Dithering: off");
+    }
+    if let Some(dither_mask) = &config.dither_mask {
+        println!("Dither mask: {dither_mask}");
+    }
+    if config.tile_flips != TileFlips::Off {
+        println!("Tile flips: {}", config.tile_flips);
+    }
+    if config.transparency {
+        println!("Transparency: alpha below {}", config.alpha_threshold);
+    }
+}
+
+/// Print what the stages of a conversion did
+fn print_stats(stats: &ConversionStats) {
+    println!(
+        "Palettes generated from {} to {} colors",
+        stats.min_palette_colors, stats.max_palette_colors
+    );
+    for (pass, changed) in stats.redither_changes.iter().enumerate() {
+        println!(
+            "Re-dithering pass {}: {} tiles changed palette",
+            pass + 1,
+            changed
+        );
+    }
+    println!(
+        "Deduplicated {} tiles into {} distinct tiles",
+        stats.tiles, stats.distinct_tiles
+    );
+    if stats.unique_tiles < stats.distinct_tiles {
+        println!(
+            "Clustered {} tiles into {} unique tiles",
+            stats.distinct_tiles, stats.unique_tiles
+        );
+    }
+}
+
+/// Convert the input image and write the output files, or only the PNG for a preview
+fn convert(config: &Config, preview: bool) -> Result<(), ConversionError> {
+    let img = image::open(&config.input_file)?;
+
+    let mut converter = ImageConverter::new(config.clone());
+    if let Some(dither_mask) = &config.dither_mask {
+        converter = converter.with_dither_mask(image::open(dither_mask)?.to_luma8());
+    }
+
+    // Keep the fitted image, so the metrics compare against what was converted
+    let fitted = converter.fit(&img)?;
+    let config = fitted.1.config();
+    print_config(config);
+
+    let tilemap_data = converter
+        .convert_fitted(std::slice::from_ref(&fitted))?
+        .remove(0);
+    print_stats(&tilemap_data.stats);
+
+    if !preview {
+        output::write_palette_hex(&tilemap_data, &config.output_palette_hex)?;
+        output::write_tilemap_hex(&tilemap_data, &config.output_tilemap_hex)?;
+        output::write_tiles_hex(&tilemap_data, &config.output_tiles_hex)?;
+
+        if let Some(mask_path) = &config.output_mask_hex {
+            output::write_mask_hex(&tilemap_data, mask_path)?;
+        }
+
+        // Write JSON if requested
+        if let Some(json_path) = &config.output_json {
+            output::write_json(&tilemap_data, json_path)?;
+        }
+
+        if let Some(bin_path) = &config.output_bin {
+            output::write_bin(&tilemap_data, bin_path)?;
+        }
+        if let Some(raw_dir) = &config.output_raw_dir {
+            write_raw_dir(&tilemap_data, Path::new(raw_dir))?;
+        }
+        if let Some(rust_path) = &config.output_rust {
+            source::write_rust(&tilemap_data, rust_path)?;
+        }
+        if let Some(c_header_path) = &config.output_c_header {
+            source::write_c_header(&tilemap_data, c_header_path)?;
+        }
+    }
+
+    let output_img = output::render_image(&tilemap_data);
+    output_img.save(&config.output_png)?;
+
+    let report = QualityReport::new(&tilemap_data, &output_img, &fitted.0);
+    if let Some(report_path) = &config.output_report {
+        report.write_json(report_path)?;
+    }
 
-    let converter = ImageConverter::new(config);
-    converter.convert()?;
+    println!();
+    println!("{}", report.metrics);
+    Ok(())
+}
+
+/// Write the headerless binary files into a directory
+fn write_raw_dir(tilemap_data: &TilemapData, dir: &Path) -> Result<(), ConversionError> {
+    std::fs::create_dir_all(dir)?;
+    output::write_palette_bin(tilemap_data, dir.join("palette.bin"))?;
+    output::write_tiles_bin(tilemap_data, dir.join("tiles.bin"))?;
+    output::write_tilemap_bin(tilemap_data, dir.join("tile_map.bin"))?;
+    if tilemap_data.config.transparency {
+        output::write_mask_bin(tilemap_data, dir.join("mask.bin"))?;
+    }
     Ok(())
 }
+
+/// Convert several images together, writing the shared palette and tiles hex
+/// files and a tilemap hex file and preview PNG per image
+fn batch(args: &BatchArgs) -> Result<(), ConversionError> {
+    let config = args.settings.config()?;
+
+    let mut images = Vec::with_capacity(args.images.len());
+    for path in args.images.iter() {
+        println!("Input: {}", path.display());
+        images.push(image::open(path)?);
+    }
+    let converter = ImageConverter::new(config.clone());
+    let fitted = converter.fit_batch(&images)?;
+    let tilemaps = converter.convert_fitted(&fitted)?;
+    print_stats(&tilemaps[0].stats);
+
+    output::write_palette_hex(&tilemaps[0], &config.output_palette_hex)?;
+    output::write_tiles_hex(&tilemaps[0], &config.output_tiles_hex)?;
+    if let Some(mask_path) = &config.output_mask_hex {
+        output::write_mask_hex(&tilemaps[0], mask_path)?;
+    }
+
+    std::fs::create_dir_all(&args.out_dir)?;
+    for ((path, (img, _)), tilemap_data) in args.images.iter().zip(&fitted).zip(&tilemaps) {
+        let name = path
+            .file_stem()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        let tilemap_path = args.out_dir.join(format!("{name}.hex"));
+        let png_path = args.out_dir.join(format!("{name}.png"));
+
+        output::write_tilemap_hex(tilemap_data, &tilemap_path)?;
+        if args.bin {
+            output::write_bin(tilemap_data, args.out_dir.join(format!("{name}.bin")))?;
+        }
+        let output_img = output::render_image(tilemap_data);
+        output_img.save(&png_path)?;
+
+        println!();
+        println!(
+            "{}: {}x{} tilemap written to {}",
+            path.display(),
+            tilemap_data.config.tilemap_width,
+            tilemap_data.config.tilemap_height,
+            tilemap_path.display()
+        );
+        // Compare against the image as it was fitted to its tilemap
+        let report = QualityReport::new(tilemap_data, &output_img, img);
+        if args.report {
+            report.write_json(args.out_dir.join(format!("{name}.report.json")))?;
+        }
+        println!("{}", report.metrics);
+    }
+    Ok(())
+}
+
+/// Convert a sprite sheet, writing its hex files and the sprite records of
+/// the layout
+fn sprites(args: &SpritesArgs) -> Result<(), ConversionError> {
+    let layout = SpriteLayout::load(&args.layout)?;
+    let img = image::open(&args.sheet)?;
+    let mut config = sprite::sheet_config(&args.settings.config()?, img.width(), img.height())?;
+    config.input_file = args.sheet.display().to_string();
+    config.output_png = args.output.display().to_string();
+    print_config(&config);
+
+    // Check the layout against the sheet before the slow part
+    let records = layout.records(&config)?;
+    let sheet = imgconv::convert(&img, &config)?;
+    print_stats(&sheet.stats);
+
+    output::write_palette_hex(&sheet, &config.output_palette_hex)?;
+    output::write_tiles_hex(&sheet, &config.output_tiles_hex)?;
+    output::write_tilemap_hex(&sheet, &args.tilemap_hex)?;
+    if let Some(mask_path) = &config.output_mask_hex {
+        output::write_mask_hex(&sheet, mask_path)?;
+    }
+    sprite::write_sprites_hex(&records, &args.sprites_hex)?;
+    output::render_image(&sheet).save(&args.output)?;
+
+    println!();
+    println!(
+        "{} sprites showing {} frames written to {}",
+        records.len(),
+        layout.frames.len(),
+        args.sprites_hex.display()
+    );
+    Ok(())
+}
+
+/// Summarise existing hex files and render what they hold
+fn inspect(args: &InspectArgs) -> Result<(), ConversionError> {
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    config.validate()?;
+
+    let assets = HexAssets::load(&args.palette_hex, &args.tiles_hex, &args.tilemap_hex)?;
+    println!("{}", assets.summary(&config));
+
+    if let Some(path) = &args.output {
+        assets.render_image(&config).save(path)?;
+        println!("Image written to {}", path.display());
+    }
+    if let Some(path) = &args.tile_sheet {
+        assets.render_tile_sheet(&config).save(path)?;
+        println!("Tile sheet written to {}", path.display());
+    }
+    if let Some(path) = &args.swatch {
+        assets.render_palette_swatch(&config).save(path)?;
+        println!("Palette swatch written to {}", path.display());
+    }
+    Ok(())
+}
+
+/// Command line interface to make it easier to use different configurations
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        None => cli
+            .convert
+            .config()
+            .and_then(|config| convert(&config, false)),
+        Some(Command::Convert(args)) => args.config().and_then(|config| convert(&config, false)),
+        Some(Command::Preview(args)) => args.config().and_then(|config| convert(&config, true)),
+        Some(Command::Batch(args)) => batch(&args),
+        Some(Command::Sprites(args)) => sprites(&args),
+        Some(Command::Inspect(args)) => inspect(&args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Quality metrics comparing a converted image with the original
+
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::{GenericImageView, Pixel, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use crate::color::{oklab_delta_e, Oklab};
+use crate::imgconv::{texel, ConversionError, TilemapData};
+
+/// Delta E multiplier for error metrics display
+pub const DELTA_E_DISPLAY_FACTOR: f32 = 100.0;
+/// Maximum pixel value for PSNR calculation (8-bit color)
+const MAX_PIXEL_VALUE: f32 = 255.0;
+/// PSNR reported for identical images, where it would be infinite. JSON has
+/// no infinity, and no real difference gets near it with 8 bit channels.
+pub const MAX_PSNR: f64 = 100.0;
+/// Size of the square windows SSIM is calculated over
+const SSIM_WINDOW: u32 = 8;
+/// Distance between the SSIM windows, so they overlap by half
+const SSIM_STEP: u32 = 4;
+/// SSIM stabilising constants, (0.01 * 255)^2 and (0.03 * 255)^2
+const SSIM_C1: f64 = 6.5025;
+const SSIM_C2: f64 = 58.5225;
+
+/// Distribution of the per pixel Delta E (scaled by `DELTA_E_DISPLAY_FACTOR`)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DeltaEStats {
+    pub min: f32,
+    pub mean: f32,
+    pub median: f32,
+    pub p75: f32,
+    pub p90: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+/// Peak signal to noise ratio of each channel, in dB
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Psnr {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+    pub average: f64,
+}
+
+/// How close a converted image is to the original
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ErrorMetrics {
+    pub delta_e: DeltaEStats,
+    pub psnr: Psnr,
+    /// Mean structural similarity of the luma, 1.0 being identical
+    pub ssim: f64,
+}
+
+impl ErrorMetrics {
+    /// Compare the output image with the original to calculate quality metrics
+    pub fn compare(output_img: &RgbImage, original_img: &image::DynamicImage) -> Self {
+        let mut delta_e_values =
+            Vec::with_capacity((output_img.width() * output_img.height()) as usize);
+
+        // Variables for PSNR calculation
+        let mut mse_r: f64 = 0.0;
+        let mut mse_g: f64 = 0.0;
+        let mut mse_b: f64 = 0.0;
+        let mut pixel_count: usize = 0;
+
+        // Calculate delta_e for each pixel and squared errors for PSNR
+        for (x, y, original_pixel) in original_img.pixels() {
+            if x >= output_img.width() || y >= output_img.height() {
+                continue;
+            }
+
+            let original_rgb = original_pixel.to_rgb();
+            let output_pixel = output_img.get_pixel(x, y);
+
+            // Calculate Delta E
+            let original_oklab = Oklab::from_rgb(original_rgb[0], original_rgb[1], original_rgb[2]);
+            let output_oklab = Oklab::from_rgb(output_pixel[0], output_pixel[1], output_pixel[2]);
+            let delta_e = oklab_delta_e(original_oklab, output_oklab) * DELTA_E_DISPLAY_FACTOR;
+            delta_e_values.push(delta_e);
+
+            // Calculate squared error for each channel (for MSE/PSNR)
+            mse_r += (original_rgb[0] as f64 - output_pixel[0] as f64).powi(2);
+            mse_g += (original_rgb[1] as f64 - output_pixel[1] as f64).powi(2);
+            mse_b += (original_rgb[2] as f64 - output_pixel[2] as f64).powi(2);
+            pixel_count += 1;
+        }
+
+        // Sort the values to calculate percentiles
+        delta_e_values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let percentile = |fraction: f32| {
+            let index = (delta_e_values.len() as f32 * fraction) as usize;
+            delta_e_values.get(index).copied().unwrap_or(0.0)
+        };
+
+        let delta_e = DeltaEStats {
+            min: delta_e_values.first().copied().unwrap_or(0.0),
+            mean: delta_e_values.iter().sum::<f32>() / delta_e_values.len().max(1) as f32,
+            median: delta_e_values
+                .get(delta_e_values.len() / 2)
+                .copied()
+                .unwrap_or(0.0),
+            p75: percentile(0.75),
+            p90: percentile(0.90),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: delta_e_values.last().copied().unwrap_or(0.0),
+        };
+
+        // Calculate PSNR for each channel and average. Images that don't
+        // overlap have nothing to compare, so they count as identical.
+        let pixel_count = pixel_count.max(1) as f64;
+        let mse_r = mse_r / pixel_count;
+        let mse_g = mse_g / pixel_count;
+        let mse_b = mse_b / pixel_count;
+        let mse_avg = (mse_r + mse_g + mse_b) / 3.0;
+
+        let psnr = Psnr {
+            red: psnr(mse_r),
+            green: psnr(mse_g),
+            blue: psnr(mse_b),
+            average: psnr(mse_avg),
+        };
+
+        ErrorMetrics {
+            delta_e,
+            psnr,
+            ssim: ssim(output_img, &original_img.to_rgb8()),
+        }
+    }
+}
+
+/// Luma of a pixel, with the Rec. 601 weights
+fn luma(pixel: &image::Rgb<u8>) -> f64 {
+    0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64
+}
+
+/// Mean SSIM of the luma over overlapping square windows of the area both
+/// images cover
+fn ssim(a: &RgbImage, b: &RgbImage) -> f64 {
+    let width = a.width().min(b.width());
+    let height = a.height().min(b.height());
+    let window = SSIM_WINDOW.min(width).min(height);
+    if window == 0 {
+        return 1.0;
+    }
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y in (0..=height - window).step_by(SSIM_STEP as usize) {
+        for x in (0..=width - window).step_by(SSIM_STEP as usize) {
+            let pixels = || {
+                (y..y + window).flat_map(move |y| {
+                    (x..x + window).map(move |x| (luma(a.get_pixel(x, y)), luma(b.get_pixel(x, y))))
+                })
+            };
+            let n = (window * window) as f64;
+            let (sum_a, sum_b) = pixels().fold((0.0, 0.0), |(sa, sb), (pa, pb)| (sa + pa, sb + pb));
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for (pa, pb) in pixels() {
+                var_a += (pa - mean_a).powi(2);
+                var_b += (pb - mean_b).powi(2);
+                covariance += (pa - mean_a) * (pb - mean_b);
+            }
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a.powi(2) + mean_b.powi(2) + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+/// Error of one tilemap position
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TileError {
+    /// Column in the tilemap
+    pub x: u32,
+    /// Row in the tilemap
+    pub y: u32,
+    /// Unique tile the entry selects
+    pub tile: usize,
+    /// Palette the entry selects
+    pub palette: usize,
+    /// Mean Delta E of the pixels (scaled by `DELTA_E_DISPLAY_FACTOR`)
+    pub mean_delta_e: f32,
+    /// Largest Delta E of a pixel (scaled by `DELTA_E_DISPLAY_FACTOR`)
+    pub max_delta_e: f32,
+}
+
+/// How much a palette is used
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PaletteUsage {
+    /// Number of tilemap entries selecting the palette
+    pub tilemap_entries: usize,
+    /// Number of pixels drawn with each color
+    pub color_pixels: Vec<usize>,
+}
+
+/// Quality of a conversion, for tracking it across changes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QualityReport {
+    #[serde(flatten)]
+    pub metrics: ErrorMetrics,
+    /// Number of unique tiles the tilemap uses
+    pub unique_tiles: usize,
+    /// Number of tile slots in the hardware
+    pub tile_slots: usize,
+    pub palette_usage: Vec<PaletteUsage>,
+    /// Error of every tilemap position, row by row
+    pub tiles: Vec<TileError>,
+}
+
+impl QualityReport {
+    /// Compare a conversion, rendered by [`crate::output::render_image`], with
+    /// the original image as it was fitted to the tilemap
+    pub fn new(
+        data: &TilemapData,
+        output_img: &RgbImage,
+        original_img: &image::DynamicImage,
+    ) -> Self {
+        let config = &data.config;
+        let original_rgb = original_img.to_rgb8();
+
+        let mut palette_usage: Vec<PaletteUsage> = data
+            .palettes
+            .iter()
+            .map(|_| PaletteUsage {
+                tilemap_entries: 0,
+                color_pixels: vec![0; config.colors_per_palette],
+            })
+            .collect();
+        for entry in data.tilemap.iter() {
+            let (Some(usage), Some(tile)) = (
+                palette_usage.get_mut(entry.palette_index),
+                data.unique_tiles.get(entry.tile_index),
+            ) else {
+                continue;
+            };
+            usage.tilemap_entries += 1;
+            for pixel_idx in 0..config.tile_size() {
+                if let Some(count) = usage
+                    .color_pixels
+                    .get_mut(texel(&tile.quantized, pixel_idx))
+                {
+                    *count += 1;
+                }
+            }
+        }
+
+        let tiles = data
+            .tilemap
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let x = i as u32 % config.tilemap_width;
+                let y = i as u32 / config.tilemap_width;
+                let mut total = 0.0;
+                let mut max: f32 = 0.0;
+                for pixel_y in y * config.tile_height..(y + 1) * config.tile_height {
+                    for pixel_x in x * config.tile_width..(x + 1) * config.tile_width {
+                        let delta_e = pixel_delta_e(output_img, &original_rgb, pixel_x, pixel_y);
+                        total += delta_e;
+                        max = max.max(delta_e);
+                    }
+                }
+                TileError {
+                    x,
+                    y,
+                    tile: entry.tile_index,
+                    palette: entry.palette_index,
+                    mean_delta_e: total / config.tile_size() as f32,
+                    max_delta_e: max,
+                }
+            })
+            .collect();
+
+        QualityReport {
+            metrics: ErrorMetrics::compare(output_img, original_img),
+            unique_tiles: data.unique_tiles.len(),
+            tile_slots: config.max_unique_tiles,
+            palette_usage,
+            tiles,
+        }
+    }
+
+    /// Write the report as JSON
+    pub fn write_json(&self, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// Delta E of a pixel (scaled by `DELTA_E_DISPLAY_FACTOR`), 0 where either
+/// image doesn't reach
+fn pixel_delta_e(a: &RgbImage, b: &RgbImage, x: u32, y: u32) -> f32 {
+    let (Some(a), Some(b)) = (a.get_pixel_checked(x, y), b.get_pixel_checked(x, y)) else {
+        return 0.0;
+    };
+    oklab_delta_e(
+        Oklab::from_rgb(a[0], a[1], a[2]),
+        Oklab::from_rgb(b[0], b[1], b[2]),
+    ) * DELTA_E_DISPLAY_FACTOR
+}
+
+/// PSNR = 20 * log10(MAX_PIXEL_VALUE) - 10 * log10(MSE), capped at `MAX_PSNR`
+fn psnr(mse: f64) -> f64 {
+    if mse > 0.0 {
+        (20.0 * (MAX_PIXEL_VALUE as f64).log10() - 10.0 * mse.log10()).min(MAX_PSNR)
+    } else {
+        MAX_PSNR
+    }
+}
+
+impl fmt::Display for ErrorMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let delta_e = &self.delta_e;
+        let psnr = &self.psnr;
+
+        writeln!(
+            f,
+            "Image Quality {DELTA_E_DISPLAY_FACTOR}x Delta E Comparison (lower is better):"
+        )?;
+        writeln!(f, "  Min:    {:6.3}", delta_e.min)?;
+        writeln!(f, "  Mean:   {:6.3}", delta_e.mean)?;
+        writeln!(f, "  Median: {:6.3}", delta_e.median)?;
+        writeln!(f, "  p75:    {:6.3}", delta_e.p75)?;
+        writeln!(f, "  p90:    {:6.3}", delta_e.p90)?;
+        writeln!(f, "  p95:    {:6.3}", delta_e.p95)?;
+        writeln!(f, "  p99:    {:6.3}", delta_e.p99)?;
+        writeln!(f, "  Max:    {:6.3}", delta_e.max)?;
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "PSNR Quality Metrics (higher is better, 30.0-50.0 is good):"
+        )?;
+        writeln!(f, "  Red channel:   {:6.3} dB", psnr.red)?;
+        writeln!(f, "  Green channel: {:6.3} dB", psnr.green)?;
+        writeln!(f, "  Blue channel:  {:6.3} dB", psnr.blue)?;
+        writeln!(f, "  Average PSNR:  {:6.3} dB", psnr.average)?;
+
+        writeln!(f)?;
+        write!(
+            f,
+            "SSIM (higher is better, 1.0 is identical): {:.4}",
+            self.ssim
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgb};
+
+    use super::*;
+
+    #[test]
+    fn identical_images_have_the_capped_psnr() {
+        let img = RgbImage::from_fn(16, 16, |x, y| Rgb([x as u8 * 16, y as u8 * 16, 128]));
+        let metrics = ErrorMetrics::compare(&img, &DynamicImage::ImageRgb8(img.clone()));
+        assert_eq!(metrics.psnr.average, MAX_PSNR);
+        assert_eq!(metrics.delta_e.max, 0.0);
+        assert!((metrics.ssim - 1.0).abs() < 1e-9);
+
+        let json = serde_json::to_value(metrics).unwrap();
+        assert_eq!(json["psnr"]["red"], MAX_PSNR);
+    }
+
+    #[test]
+    fn empty_images_have_finite_metrics() {
+        let empty = RgbImage::new(0, 0);
+        let metrics = ErrorMetrics::compare(&empty, &DynamicImage::ImageRgb8(empty.clone()));
+        assert_eq!(metrics.psnr.average, MAX_PSNR);
+        assert_eq!(metrics.delta_e.mean, 0.0);
+        assert_eq!(metrics.ssim, 1.0);
+    }
+
+    #[test]
+    fn psnr_falls_as_the_error_grows() {
+        assert_eq!(psnr(0.0), MAX_PSNR);
+        assert!((psnr(1.0) - 48.13).abs() < 0.01);
+        assert!(psnr(100.0) < psnr(1.0));
+    }
+}
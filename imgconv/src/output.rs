@@ -0,0 +1,423 @@
+//! Writers for converted tilemaps
+//!
+//! These turn a [`TilemapData`] into the files the RTL and the emulator load,
+//! and into an image of what the hardware will display.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+use crate::binfile::BinaryAssets;
+use crate::imgconv::{
+    flipped_pixel, texel, Config, ConversionError, TilemapData, UniqueTile, TRANSPARENT_INDEX,
+};
+
+/// Number of mask bits in each word of the mask file
+const MASK_BITS_PER_WORD: usize = 16;
+
+/// How finely the transparency mask covers a tile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MaskGranularity {
+    /// One bit per texel
+    #[default]
+    Pixel,
+    /// One bit per doubled pixel on screen, two per texel
+    SubPixel,
+}
+
+impl MaskGranularity {
+    /// Number of mask bits each texel takes
+    fn bits_per_texel(self) -> usize {
+        match self {
+            MaskGranularity::Pixel => 1,
+            MaskGranularity::SubPixel => 2,
+        }
+    }
+}
+
+impl FromStr for MaskGranularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pixel" => Ok(MaskGranularity::Pixel),
+            "sub-pixel" => Ok(MaskGranularity::SubPixel),
+            _ => Err(format!("{s} is not one of pixel or sub-pixel")),
+        }
+    }
+}
+
+impl fmt::Display for MaskGranularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskGranularity::Pixel => f.write_str("pixel"),
+            MaskGranularity::SubPixel => f.write_str("sub-pixel"),
+        }
+    }
+}
+
+/// The palette RAM words: a 24 bit `rrggbb` color for every color of every
+/// palette, missing colors padded with zeros
+///
+/// At a lower color depth the low bits repeat the high ones, so the top bits
+/// of each channel are the value to store.
+pub fn palette_words(data: &TilemapData) -> Vec<u32> {
+    let mut words = Vec::with_capacity(data.palettes.len() * data.config.colors_per_palette);
+
+    for palette in data.palettes.iter() {
+        for color in palette.colors.iter() {
+            let (r, g, b) = color.color.to_rgb();
+            words.push((r as u32) << 16 | (g as u32) << 8 | b as u32);
+        }
+
+        // Pad with zeros for missing colors
+        let missing = data
+            .config
+            .colors_per_palette
+            .saturating_sub(palette.colors.len());
+        words.extend(std::iter::repeat_n(0, missing));
+    }
+
+    words
+}
+
+/// The tilemap entries, row by row
+pub fn tilemap_words(data: &TilemapData) -> Vec<u16> {
+    data.tilemap.iter().map(|entry| entry.raw_value).collect()
+}
+
+/// The texel words of the unique tiles, padded to `max_unique_tiles`
+///
+/// Row-interleaved: row 0 of every tile, then row 1 of every tile and so on,
+/// with [`Config::chunks_per_row`] chunks per row of each tile.
+pub fn tile_words(data: &TilemapData) -> Vec<u16> {
+    let chunks_per_row = data.config.chunks_per_row();
+    let tile_slots = data.unique_tiles.len().max(data.config.max_unique_tiles);
+    let mut words =
+        Vec::with_capacity(data.config.tile_height as usize * tile_slots * chunks_per_row);
+
+    // For each row of the tile (0-7 for 8x8 tiles)
+    for row in 0..data.config.tile_height as usize {
+        // For each unique tile
+        for tile in data.unique_tiles.iter() {
+            let row_start = row * chunks_per_row;
+
+            // The chunks for this row of this tile
+            for chunk_offset in 0..chunks_per_row {
+                words.push(
+                    tile.quantized
+                        .get(row_start + chunk_offset)
+                        .copied()
+                        .unwrap_or(0),
+                );
+            }
+        }
+
+        // Pad remaining tiles if fewer than max_unique_tiles
+        let missing = data
+            .config
+            .max_unique_tiles
+            .saturating_sub(data.unique_tiles.len());
+        words.extend(std::iter::repeat_n(0, missing * chunks_per_row));
+    }
+
+    words
+}
+
+/// Write words to a hex file, `per_line` words to a line
+fn write_hex(
+    words: impl IntoIterator<Item = u32>,
+    digits: usize,
+    per_line: usize,
+    path: impl AsRef<Path>,
+) -> Result<(), ConversionError> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    for (i, word) in words.into_iter().enumerate() {
+        write!(&mut file, "{word:0digits$x} ")?;
+        if i % per_line == per_line - 1 {
+            writeln!(&mut file)?;
+        }
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+/// Write words to a binary file, little-endian with no header
+fn write_raw<const N: usize>(
+    words: impl IntoIterator<Item = [u8; N]>,
+    path: impl AsRef<Path>,
+) -> Result<(), ConversionError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for word in words {
+        file.write_all(&word)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Write palette data to hex file, a line per palette
+///
+/// Colors are always written as 24 bit `rrggbb`, see [`palette_words`].
+pub fn write_palette_hex(
+    data: &TilemapData,
+    path: impl AsRef<Path>,
+) -> Result<(), ConversionError> {
+    write_hex(palette_words(data), 6, data.config.colors_per_palette, path)
+}
+
+/// Write tilemap data to hex file
+pub fn write_tilemap_hex(
+    data: &TilemapData,
+    path: impl AsRef<Path>,
+) -> Result<(), ConversionError> {
+    write_hex(
+        tilemap_words(data).into_iter().map(u32::from),
+        4,
+        data.config.tilemap_width as usize,
+        path,
+    )
+}
+
+/// Write tile data to hex file (unique tiles only)
+///
+/// Output format: 8 lines (one per tile row 0-7)
+/// Each line: for each unique tile, write 2 chunks for that row
+pub fn write_tiles_hex(data: &TilemapData, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+    let words = tile_words(data);
+    let per_line = words.len() / data.config.tile_height as usize;
+    write_hex(words.into_iter().map(u32::from), 4, per_line, path)
+}
+
+/// Write palette data to a raw binary file, a little-endian `0x00rrggbb`
+/// word per color
+pub fn write_palette_bin(
+    data: &TilemapData,
+    path: impl AsRef<Path>,
+) -> Result<(), ConversionError> {
+    write_raw(palette_words(data).into_iter().map(u32::to_le_bytes), path)
+}
+
+/// Write tilemap data to a raw binary file of little-endian 16 bit entries
+pub fn write_tilemap_bin(
+    data: &TilemapData,
+    path: impl AsRef<Path>,
+) -> Result<(), ConversionError> {
+    write_raw(tilemap_words(data).into_iter().map(u16::to_le_bytes), path)
+}
+
+/// Write tile data to a raw binary file of little-endian 16 bit words, laid
+/// out like the tiles hex file
+pub fn write_tiles_bin(data: &TilemapData, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+    write_raw(tile_words(data).into_iter().map(u16::to_le_bytes), path)
+}
+
+/// Number of mask words per tile
+pub fn mask_words_per_tile(config: &Config) -> usize {
+    (config.tile_size() * config.mask_granularity.bits_per_texel()).div_ceil(MASK_BITS_PER_WORD)
+}
+
+/// Build the transparency mask of a tile, set bits being texels that are drawn
+///
+/// Bits go left to right then top to bottom, starting from the least
+/// significant bit of the first word, like the texels in the tile data.
+/// Without transparency every texel is drawn.
+pub fn tile_mask(tile: &UniqueTile, config: &Config) -> Vec<u16> {
+    let bits_per_texel = config.mask_granularity.bits_per_texel();
+    let mut mask = vec![0u16; mask_words_per_tile(config)];
+
+    for pixel_idx in 0..config.tile_size() {
+        if config.transparency && texel(&tile.quantized, pixel_idx) == TRANSPARENT_INDEX {
+            continue;
+        }
+
+        for sub_pixel in 0..bits_per_texel {
+            let bit = pixel_idx * bits_per_texel + sub_pixel;
+            mask[bit / MASK_BITS_PER_WORD] |= 1 << (bit % MASK_BITS_PER_WORD);
+        }
+    }
+
+    mask
+}
+
+/// The transparency mask words of the unique tiles, padded to
+/// `max_unique_tiles`
+///
+/// Laid out like the tile words: word N of every tile slot, then word N + 1.
+pub fn mask_words(data: &TilemapData) -> Vec<u16> {
+    let masks: Vec<Vec<u16>> = data
+        .unique_tiles
+        .iter()
+        .map(|tile| tile_mask(tile, &data.config))
+        .collect();
+    let mut words = Vec::new();
+
+    for word in 0..mask_words_per_tile(&data.config) {
+        words.extend(masks.iter().map(|mask| mask[word]));
+
+        // Pad remaining tiles if fewer than max_unique_tiles
+        let missing = data.config.max_unique_tiles.saturating_sub(masks.len());
+        words.extend(std::iter::repeat_n(0, missing));
+    }
+
+    words
+}
+
+/// Write the transparency masks of the unique tiles to hex file
+///
+/// Laid out like the tiles hex file: line N has mask word N of every tile
+/// slot, so for 8x8 tiles at sub-pixel granularity each line is a tile row.
+pub fn write_mask_hex(data: &TilemapData, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+    let words = mask_words(data);
+    let per_line = words.len() / mask_words_per_tile(&data.config);
+    write_hex(words.into_iter().map(u32::from), 4, per_line, path)
+}
+
+/// Write the transparency masks to a raw binary file of little-endian 16 bit
+/// words, laid out like the mask hex file
+pub fn write_mask_bin(data: &TilemapData, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+    write_raw(mask_words(data).into_iter().map(u16::to_le_bytes), path)
+}
+
+/// Write the binary asset file: a header, then the palette, tiles, tilemap
+/// and transparency mask words, see [`crate::binfile`]
+pub fn write_bin(data: &TilemapData, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+    std::fs::write(path, BinaryAssets::new(data).to_bytes())?;
+    Ok(())
+}
+
+/// Write the whole tilemap data as JSON
+pub fn write_json(data: &TilemapData, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+    let file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(file, data)?;
+    Ok(())
+}
+
+/// Render the tilemap the way the hardware will display it
+pub fn render_image(data: &TilemapData) -> RgbImage {
+    let config = &data.config;
+    let mut out_img = RgbImage::new(config.total_width(), config.total_height());
+
+    for y in 0..config.tilemap_height {
+        for x in 0..config.tilemap_width {
+            let entry = &data.tilemap[(y * config.tilemap_width + x) as usize];
+            let (Some(unique_tile), Some(palette)) = (
+                data.unique_tiles.get(entry.tile_index),
+                data.palettes.get(entry.palette_index),
+            ) else {
+                continue;
+            };
+
+            // Render this tile, mirrored as the entry says
+            for pixel_idx in 0..config.tile_size() {
+                let source_idx = flipped_pixel(config, pixel_idx, entry.x_flip, entry.y_flip);
+                let color_idx = texel(&unique_tile.quantized, source_idx);
+                if let Some(color) = palette.colors.get(color_idx) {
+                    let (r, g, b) = color.color.to_rgb();
+
+                    let pixel_y = pixel_idx / config.tile_width as usize;
+                    let pixel_x = pixel_idx % config.tile_width as usize;
+
+                    out_img.put_pixel(
+                        x * config.tile_width + pixel_x as u32,
+                        y * config.tile_height + pixel_y as u32,
+                        image::Rgb([r, g, b]),
+                    );
+                }
+            }
+        }
+    }
+
+    out_img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8x8 tile of color 1, with transparent texels at the ends of the
+    /// first 4 texels
+    fn tile() -> UniqueTile {
+        let mut quantized = vec![0x1111; 16];
+        quantized[0] = 0x0110;
+        UniqueTile {
+            quantized,
+            source_tile: 0,
+        }
+    }
+
+    #[test]
+    fn tile_rows_hold_every_chunk_of_wide_tiles() {
+        let img = image::DynamicImage::ImageRgb8(RgbImage::from_fn(32, 8, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 32) as u8, 40])
+        }));
+        let config = Config {
+            tile_width: 16,
+            num_palettes: 1,
+            dithering: false,
+            ..Config::default()
+        };
+        let data = crate::imgconv::convert(&img, &config).unwrap();
+        let words = tile_words(&data);
+
+        // 4 chunks per row of each of the 256 tile slots
+        let row_stride = config.max_unique_tiles * 4;
+        assert_eq!(words.len(), 8 * row_stride);
+        for (slot, tile) in data.unique_tiles.iter().enumerate() {
+            for row in 0..8 {
+                let start = row * row_stride + slot * 4;
+                assert_eq!(
+                    words[start..start + 4],
+                    tile.quantized[row * 4..row * 4 + 4]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn masks_have_a_bit_per_drawn_texel() {
+        let config = Config {
+            transparency: true,
+            ..Config::default()
+        };
+        assert_eq!(mask_words_per_tile(&config), 4);
+        assert_eq!(
+            tile_mask(&tile(), &config),
+            [0xfff6, 0xffff, 0xffff, 0xffff]
+        );
+    }
+
+    #[test]
+    fn sub_pixel_masks_have_a_bit_per_doubled_pixel() {
+        let config = Config {
+            transparency: true,
+            mask_granularity: MaskGranularity::SubPixel,
+            ..Config::default()
+        };
+        assert_eq!(mask_words_per_tile(&config), 8);
+        let mask = tile_mask(&tile(), &config);
+        assert_eq!(mask[0], 0xff3c);
+        assert!(mask[1..].iter().all(|&word| word == 0xffff));
+    }
+
+    #[test]
+    fn every_texel_is_drawn_without_transparency() {
+        let mask = tile_mask(&tile(), &Config::default());
+        assert!(mask.iter().all(|&word| word == 0xffff));
+    }
+
+    #[test]
+    fn granularities_parse_as_displayed() {
+        for granularity in [MaskGranularity::Pixel, MaskGranularity::SubPixel] {
+            assert_eq!(granularity.to_string().parse(), Ok(granularity));
+        }
+        assert!("texel".parse::<MaskGranularity>().is_err());
+    }
+}
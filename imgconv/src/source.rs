@@ -0,0 +1,241 @@
+//! Rust and C source output for compiling assets into firmware
+//!
+//! The arrays hold the same words as the hex files, so firmware can copy them
+//! into the palette RAM and VRAM as they are.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::imgconv::{ConversionError, TilemapData};
+use crate::output;
+
+/// Number of array words written on each line
+const WORDS_PER_LINE: usize = 8;
+
+/// The words of one array and how wide each is
+struct Array {
+    name: &'static str,
+    bits: u32,
+    words: Vec<u32>,
+}
+
+/// The arrays to write: the palette, tiles, tilemap and, with transparency,
+/// the mask
+fn arrays(data: &TilemapData) -> Vec<Array> {
+    let widen = |words: Vec<u16>| words.into_iter().map(u32::from).collect();
+    let mut arrays = vec![
+        Array {
+            name: "PALETTE",
+            bits: 32,
+            words: output::palette_words(data),
+        },
+        Array {
+            name: "TILES",
+            bits: 16,
+            words: widen(output::tile_words(data)),
+        },
+        Array {
+            name: "TILEMAP",
+            bits: 16,
+            words: widen(output::tilemap_words(data)),
+        },
+    ];
+    if data.config.transparency {
+        arrays.push(Array {
+            name: "MASK",
+            bits: 16,
+            words: widen(output::mask_words(data)),
+        });
+    }
+    arrays
+}
+
+/// The dimensions to write as constants
+fn constants(data: &TilemapData) -> [(&'static str, usize); 6] {
+    let config = &data.config;
+    [
+        ("TILE_WIDTH", config.tile_width as usize),
+        ("TILE_HEIGHT", config.tile_height as usize),
+        ("TILEMAP_WIDTH", config.tilemap_width as usize),
+        ("TILEMAP_HEIGHT", config.tilemap_height as usize),
+        ("PALETTE_COUNT", data.palettes.len()),
+        ("COLORS_PER_PALETTE", config.colors_per_palette),
+    ]
+}
+
+/// Format the words of an array as hex literals, a few to a line
+fn array_body(array: &Array) -> String {
+    let digits = array.bits as usize / 4;
+    let mut body = String::new();
+    for line in array.words.chunks(WORDS_PER_LINE) {
+        body.push_str("   ");
+        for word in line {
+            let _ = write!(body, " 0x{word:0digits$x},");
+        }
+        body.push('\n');
+    }
+    body
+}
+
+/// Write a Rust module with the dimensions as `usize` constants and
+/// `PALETTE: [u32; N]`, `TILES: [u16; N]`, `TILEMAP: [u16; N]` and, with
+/// transparency, `MASK: [u16; N]` arrays
+pub fn write_rust(data: &TilemapData, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+    let mut source = String::from("// Generated by imgconv, do not edit.\n\n");
+
+    for (name, value) in constants(data) {
+        let _ = writeln!(source, "pub const {name}: usize = {value};");
+    }
+
+    for array in arrays(data) {
+        let _ = writeln!(
+            source,
+            "\npub const {}: [u{}; {}] = [",
+            array.name,
+            array.bits,
+            array.words.len()
+        );
+        source.push_str(&array_body(&array));
+        source.push_str("];\n");
+    }
+
+    std::fs::write(path, source)?;
+    Ok(())
+}
+
+/// Write a C header with the dimensions as macros and `uint32_t` palette and
+/// `uint16_t` tiles, tilemap and mask arrays
+///
+/// Everything is prefixed with the file name, so `finch.h` has
+/// `FINCH_TILEMAP_WIDTH` and `finch_palette`, and headers for several images
+/// can be included together.
+pub fn write_c_header(data: &TilemapData, path: impl AsRef<Path>) -> Result<(), ConversionError> {
+    let path = path.as_ref();
+    let prefix = c_identifier(
+        &path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default(),
+    );
+    let mut source = String::from("/* Generated by imgconv, do not edit. */\n\n");
+
+    let _ = writeln!(source, "#ifndef {prefix}_H");
+    let _ = writeln!(source, "#define {prefix}_H\n");
+    source.push_str("#include <stdint.h>\n\n");
+
+    for (name, value) in constants(data) {
+        let _ = writeln!(source, "#define {prefix}_{name} {value}");
+    }
+
+    for array in arrays(data) {
+        let _ = writeln!(
+            source,
+            "\nstatic const uint{}_t {}_{}[{}] = {{",
+            array.bits,
+            prefix.to_lowercase(),
+            array.name.to_lowercase(),
+            array.words.len()
+        );
+        source.push_str(&array_body(&array));
+        source.push_str("};\n");
+    }
+
+    let _ = writeln!(source, "\n#endif /* {prefix}_H */");
+
+    std::fs::write(path, source)?;
+    Ok(())
+}
+
+/// Turn a file name into an upper case C identifier
+fn c_identifier(name: &str) -> String {
+    let mut identifier: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !identifier.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        identifier.insert_str(0, "ASSETS_");
+    }
+    identifier
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    use super::*;
+    use crate::imgconv::{convert, Config};
+
+    fn data() -> TilemapData {
+        let img = RgbImage::from_fn(16, 8, |x, y| Rgb([(x * 16) as u8, (y * 32) as u8, 0]));
+        let config = Config {
+            num_palettes: 1,
+            max_unique_tiles: 4,
+            ..Config::default()
+        };
+        convert(&DynamicImage::ImageRgb8(img), &config).unwrap()
+    }
+
+    fn scratch_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("imgconv-source-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn arrays_are_hex_literals_a_few_to_a_line() {
+        let array = Array {
+            name: "TILES",
+            bits: 16,
+            words: (0..10).collect(),
+        };
+        assert_eq!(
+            array_body(&array),
+            "    0x0000, 0x0001, 0x0002, 0x0003, 0x0004, 0x0005, 0x0006, 0x0007,\n    0x0008, 0x0009,\n"
+        );
+    }
+
+    #[test]
+    fn rust_modules_hold_the_hex_file_words() {
+        let data = data();
+        let path = scratch_path("assets.rs");
+        write_rust(&data, &path).unwrap();
+        let source = std::fs::read_to_string(path).unwrap();
+
+        assert!(source.contains("pub const TILEMAP_WIDTH: usize = 2;\n"));
+        assert!(source.contains("pub const PALETTE_COUNT: usize = 1;\n"));
+        assert!(source.contains("pub const PALETTE: [u32; 16] = [\n"));
+        assert!(source.contains("pub const TILES: [u16; 64] = [\n"));
+        assert!(source.contains(&format!(
+            "pub const TILEMAP: [u16; 2] = [\n    0x{:04x}, 0x{:04x},\n];\n",
+            data.tilemap[0].raw_value, data.tilemap[1].raw_value
+        )));
+        assert!(!source.contains("MASK"));
+    }
+
+    #[test]
+    fn c_headers_are_prefixed_with_the_file_name() {
+        let path = scratch_path("title-screen.h");
+        write_c_header(&data(), &path).unwrap();
+        let source = std::fs::read_to_string(path).unwrap();
+
+        assert!(source.contains("#ifndef TITLE_SCREEN_H\n"));
+        assert!(source.contains("#define TITLE_SCREEN_TILE_HEIGHT 8\n"));
+        assert!(source.contains("static const uint32_t title_screen_palette[16] = {\n"));
+        assert!(source.contains("static const uint16_t title_screen_tilemap[2] = {\n"));
+        assert!(source.ends_with("#endif /* TITLE_SCREEN_H */\n"));
+    }
+
+    #[test]
+    fn file_names_become_c_identifiers() {
+        assert_eq!(c_identifier("finch"), "FINCH");
+        assert_eq!(c_identifier("level 1.v2"), "LEVEL_1_V2");
+        assert_eq!(c_identifier("2d"), "ASSETS_2D");
+        assert_eq!(c_identifier(""), "ASSETS_");
+    }
+}
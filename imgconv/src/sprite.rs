@@ -0,0 +1,381 @@
+//! Sprite attribute records for the sprite BRAM
+//!
+//! A sprite shows a rectangle of a tilemap at a position on screen. The
+//! layout of a sprite sheet names rectangles of its tilemap as frames, and
+//! places sprites showing those frames. Each sprite becomes a 144 bit record
+//! of the four packed structs in rtl/sprite_types.sv, `sprite_y_height_t` in
+//! the least significant 36 bits, then `sprite_x_width_t`, `sprite_addr_t`
+//! and `sprite_velocity_t`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fit::{Anchor, FitMode};
+use crate::imgconv::{Config, ConversionError};
+
+/// Number of sprites in the sprite BRAM
+pub const MAX_SPRITES: usize = 512;
+/// Widest tilemap a sprite can address, in tiles
+pub const MAX_TILEMAP_WIDTH: u32 = 256;
+/// Number of tile slots the hardware's texel rows are interleaved over
+pub const HARDWARE_TILE_SLOTS: usize = 256;
+
+/// Largest value of the 8 bit tile fields
+const MAX_TILE_FIELD: u32 = 0xff;
+/// Range of the 12 bit two's complement screen coordinates, in pixels
+const SCREEN_COORD_RANGE: std::ops::Range<i32> = -2048..2048;
+/// Range of the 18 bit two's complement velocities, in 1/16ths of a pixel
+const VELOCITY_RANGE: std::ops::Range<i32> = -(1 << 17)..(1 << 17);
+/// Mask for an 18 bit address or velocity field
+const FIELD_18_MASK: u64 = (1 << 18) - 1;
+
+/// A rectangle of the sprite sheet's tilemap, in tiles
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Frame {
+    /// Column of the left of the frame
+    pub x: u32,
+    /// Row of the top of the frame
+    pub y: u32,
+    /// Width in tiles
+    pub width: u32,
+    /// Height in tiles
+    pub height: u32,
+}
+
+/// A sprite showing a frame somewhere on screen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpritePlacement {
+    /// Name of the frame to show
+    pub frame: String,
+    /// Screen x coordinate of the left of the sprite, in pixels
+    pub x: i32,
+    /// Screen y coordinate of the top of the sprite, in pixels
+    pub y: i32,
+    /// Mirror the sprite horizontally
+    #[serde(default)]
+    pub x_flip: bool,
+    /// Mirror the sprite vertically
+    #[serde(default)]
+    pub y_flip: bool,
+    /// Horizontal movement per frame once sprites start moving, in 1/16ths
+    /// of a pixel
+    #[serde(default)]
+    pub x_velocity: i32,
+    /// Vertical movement per frame, in 1/16ths of a pixel
+    #[serde(default)]
+    pub y_velocity: i32,
+}
+
+/// Frames of a sprite sheet, and the sprites to show
+///
+/// Loaded from JSON or TOML, e.g.
+///
+/// ```toml
+/// [frames.walk]
+/// x = 0
+/// y = 0
+/// width = 2
+/// height = 4
+///
+/// [[sprites]]
+/// frame = "walk"
+/// x = 100
+/// y = 200
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpriteLayout {
+    /// Word address of the sheet's tilemap, in 512 word units
+    #[serde(default)]
+    pub tilemap_addr: u32,
+    /// Word address of the sheet's tile bitmaps
+    #[serde(default)]
+    pub tile_bitmap_addr: u32,
+    /// Named rectangles of the sheet's tilemap
+    pub frames: BTreeMap<String, Frame>,
+    /// Sprites in drawing order, later sprites are drawn over earlier ones
+    pub sprites: Vec<SpritePlacement>,
+}
+
+impl SpriteLayout {
+    /// Load a layout from a JSON or TOML file, chosen by its extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConversionError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(serde_json::from_str(&text)?),
+            Some("toml") => Ok(toml::from_str(&text)?),
+            _ => Err(ConversionError::InvalidConfig(format!(
+                "{} is not a .json or .toml file",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Pack a record for every sprite, showing frames of a sheet converted
+    /// with the given configuration (see [`sheet_config`])
+    pub fn records(&self, config: &Config) -> Result<Vec<[u64; 4]>, ConversionError> {
+        let invalid = |message: String| Err(ConversionError::InvalidConfig(message));
+
+        if self.sprites.len() > MAX_SPRITES {
+            return invalid(format!(
+                "{} sprites is more than the {MAX_SPRITES} the sprite BRAM holds",
+                self.sprites.len()
+            ));
+        }
+        if config.tile_width != 8 || config.tile_height != 8 {
+            return invalid("sprite sheets must have 8x8 tiles".to_string());
+        }
+        if config.max_unique_tiles != HARDWARE_TILE_SLOTS {
+            return invalid(format!(
+                "sprite sheets must have {HARDWARE_TILE_SLOTS} tile slots, the texel row \
+                 stride of the hardware"
+            ));
+        }
+        let Some((size_a, size_b)) = tilemap_size_bits(config.tilemap_width) else {
+            return invalid(format!(
+                "a {} tile wide tilemap can't be addressed by sprites, see tilemap_stride",
+                config.tilemap_width
+            ));
+        };
+        if self.tilemap_addr as u64 > FIELD_18_MASK || self.tile_bitmap_addr as u64 > FIELD_18_MASK
+        {
+            return invalid("tilemap_addr and tile_bitmap_addr must fit in 18 bits".to_string());
+        }
+
+        let mut records = Vec::with_capacity(self.sprites.len());
+        for (index, sprite) in self.sprites.iter().enumerate() {
+            let Some(frame) = self.frames.get(&sprite.frame) else {
+                return invalid(format!(
+                    "sprite {index} shows frame {}, which isn't defined",
+                    sprite.frame
+                ));
+            };
+            if frame.width == 0
+                || frame.height == 0
+                || frame.width > MAX_TILE_FIELD
+                || frame.height > MAX_TILE_FIELD
+                || frame.x.saturating_add(frame.width) > config.tilemap_width
+                || frame.y.saturating_add(frame.height) > config.tilemap_height
+                || frame.y > MAX_TILE_FIELD
+            {
+                return invalid(format!(
+                    "frame {} ({}x{} tiles at {},{}) isn't inside the {}x{} sheet",
+                    sprite.frame,
+                    frame.width,
+                    frame.height,
+                    frame.x,
+                    frame.y,
+                    config.tilemap_width,
+                    config.tilemap_height
+                ));
+            }
+            if !SCREEN_COORD_RANGE.contains(&sprite.x) || !SCREEN_COORD_RANGE.contains(&sprite.y) {
+                return invalid(format!(
+                    "sprite {index} at {},{} is outside the 12 bit screen coordinates",
+                    sprite.x, sprite.y
+                ));
+            }
+            if !VELOCITY_RANGE.contains(&sprite.x_velocity)
+                || !VELOCITY_RANGE.contains(&sprite.y_velocity)
+            {
+                return invalid(format!("sprite {index}'s velocity doesn't fit in 18 bits"));
+            }
+
+            let record = SpriteRecord {
+                tilemap_size_a: size_a,
+                tilemap_size_b: size_b,
+                height: frame.height,
+                tilemap_y: frame.y,
+                screen_y: sprite.y,
+                width: frame.width,
+                tilemap_x: frame.x,
+                screen_x: sprite.x,
+                x_flip: sprite.x_flip,
+                y_flip: sprite.y_flip,
+                tilemap_addr: self.tilemap_addr,
+                tile_bitmap_addr: self.tile_bitmap_addr,
+                x_velocity: sprite.x_velocity,
+                y_velocity: sprite.y_velocity,
+            };
+            records.push(record.pack());
+        }
+
+        Ok(records)
+    }
+}
+
+/// The fields of one sprite BRAM entry
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpriteRecord {
+    /// The tilemap is `(1 << (a + 4)) + (1 << (b + 4))` tiles wide
+    pub tilemap_size_a: u32,
+    pub tilemap_size_b: u32,
+    /// Height in tiles
+    pub height: u32,
+    /// Row of the tilemap shown at the top of the sprite
+    pub tilemap_y: u32,
+    /// Screen y coordinate of the top, in pixels
+    pub screen_y: i32,
+    /// Width in tiles
+    pub width: u32,
+    /// Column of the tilemap shown at the left of the sprite
+    pub tilemap_x: u32,
+    /// Screen x coordinate of the left, in pixels
+    pub screen_x: i32,
+    /// Mirror the sprite horizontally
+    pub x_flip: bool,
+    /// Mirror the sprite vertically
+    pub y_flip: bool,
+    /// Address of the tilemap in 512 word units
+    pub tilemap_addr: u32,
+    /// Word address of the tile bitmaps
+    pub tile_bitmap_addr: u32,
+    /// Movement per frame, in 1/16ths of a pixel
+    pub x_velocity: i32,
+    pub y_velocity: i32,
+}
+
+impl SpriteRecord {
+    /// Pack the record bit for bit like the sprite BRAM, as its four 36 bit
+    /// structs starting from the least significant
+    pub fn pack(&self) -> [u64; 4] {
+        // screen coordinates are 12.4 fixed point, with no fraction here
+        let position = |pixels: i32| ((pixels as u64) & 0xfff) << 4;
+        let field_8 = |value: u32| (value as u64) & 0xff;
+
+        let y_height = (self.tilemap_size_a as u64 & 3) << 34
+            | (self.tilemap_size_b as u64 & 3) << 32
+            | field_8(self.height) << 24
+            | field_8(self.tilemap_y) << 16
+            | position(self.screen_y);
+        let x_width = (self.y_flip as u64) << 33
+            | (self.x_flip as u64) << 32
+            | field_8(self.width) << 24
+            | field_8(self.tilemap_x) << 16
+            | position(self.screen_x);
+        let addr = (self.tilemap_addr as u64 & FIELD_18_MASK) << 18
+            | self.tile_bitmap_addr as u64 & FIELD_18_MASK;
+        let velocity =
+            (self.x_velocity as u64 & FIELD_18_MASK) << 18 | self.y_velocity as u64 & FIELD_18_MASK;
+
+        [y_height, x_width, addr, velocity]
+    }
+}
+
+/// The `tilemap_size_a` and `tilemap_size_b` fields for a tilemap width, the
+/// width being `(1 << (a + 4)) + (1 << (b + 4))` tiles
+pub fn tilemap_size_bits(width: u32) -> Option<(u32, u32)> {
+    (0..4)
+        .flat_map(|a| (a..4).map(move |b| (a, b)))
+        .find(|&(a, b)| (1 << (a + 4)) + (1 << (b + 4)) == width)
+}
+
+/// The narrowest tilemap width sprites can address that is at least the
+/// given width, if there is one
+pub fn tilemap_stride(width: u32) -> Option<u32> {
+    (width..=MAX_TILEMAP_WIDTH).find(|&stride| tilemap_size_bits(stride).is_some())
+}
+
+/// The configuration to convert a sprite sheet of the given size with
+///
+/// The sheet is used unscaled, with its tilemap padded on the right to the
+/// next width sprites can address.
+pub fn sheet_config(config: &Config, width: u32, height: u32) -> Result<Config, ConversionError> {
+    let columns = width.div_ceil(config.tile_width.max(1));
+    let Some(stride) = tilemap_stride(columns) else {
+        return Err(ConversionError::InvalidConfig(format!(
+            "a {columns} tile wide sprite sheet is wider than the {MAX_TILEMAP_WIDTH} tiles \
+             sprites can address"
+        )));
+    };
+
+    let mut config = config.clone();
+    config.fit = FitMode::Pad;
+    config.anchor = Anchor::TopLeft;
+    config.tilemap_width = stride;
+    config.tilemap_height = height.div_ceil(config.tile_height.max(1));
+    config.validate()?;
+    Ok(config)
+}
+
+/// Write sprite records to a hex file, one 144 bit record per line
+pub fn write_sprites_hex(
+    records: &[[u64; 4]],
+    path: impl AsRef<Path>,
+) -> Result<(), ConversionError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for record in records {
+        for field in record.iter().rev() {
+            write!(&mut file, "{field:09x}")?;
+        }
+        writeln!(&mut file)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_pack_like_the_sprite_types() {
+        let record = SpriteRecord {
+            tilemap_size_a: 1,
+            tilemap_size_b: 3,
+            height: 0x12,
+            tilemap_y: 0x34,
+            screen_y: 0x567,
+            width: 0x89,
+            tilemap_x: 0xab,
+            screen_x: 0x3cd,
+            x_flip: true,
+            y_flip: false,
+            tilemap_addr: 0x2_0001,
+            tile_bitmap_addr: 0x1_fffe,
+            x_velocity: 5,
+            y_velocity: -3,
+        };
+
+        // sprite_y_height_t: size_a[35:34] size_b[33:32] height[31:24]
+        // tilemap_y[23:16] screen_y[15:4] screen_sub_y[3:0]
+        // sprite_x_width_t: unused[35:34] y_flip[33] x_flip[32] width[31:24]
+        // tilemap_x[23:16] screen_x[15:4] screen_sub_x[3:0]
+        // sprite_addr_t: tilemap_addr[35:18] tile_bitmap_addr[17:0]
+        // sprite_velocity_t: x_velocity[35:18] y_velocity[17:0]
+        assert_eq!(
+            record.pack(),
+            [0x7_1234_5670, 0x1_89ab_3cd0, 0x8_0005_fffe, 0x0_0017_fffd]
+        );
+    }
+
+    #[test]
+    fn negative_positions_wrap_to_twelve_bits() {
+        let record = SpriteRecord {
+            screen_x: -1,
+            screen_y: -2048,
+            ..Default::default()
+        };
+        let [y_height, x_width, ..] = record.pack();
+        assert_eq!(y_height, 0x800 << 4);
+        assert_eq!(x_width, 0xfff << 4);
+    }
+
+    #[test]
+    fn tilemap_widths_sprites_can_address() {
+        assert_eq!(tilemap_size_bits(32), Some((0, 0)));
+        assert_eq!(tilemap_size_bits(160), Some((1, 3)));
+        assert_eq!(tilemap_size_bits(256), Some((3, 3)));
+        assert_eq!(tilemap_size_bits(100), None);
+
+        assert_eq!(tilemap_stride(1), Some(32));
+        assert_eq!(tilemap_stride(90), Some(96));
+        assert_eq!(tilemap_stride(160), Some(160));
+        assert_eq!(tilemap_stride(257), None);
+    }
+}
@@ -0,0 +1,217 @@
+//! Headless frame rendering
+//!
+//! Steps the VDP for a number of frames without opening a window, writing
+//! the selected frames to PNG files so they can be diffed against reference
+//! images on machines without a display.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::RgbaImage;
+
+use crate::stream::CommandStream;
+use crate::timing::{busiest_line, LineStats};
+use crate::vdp::VdpState;
+
+/// Settings for a headless run
+#[derive(Debug, Clone)]
+pub struct HeadlessConfig {
+    /// Number of frames to step
+    pub frames: u64,
+    /// Directory the PNG files are written to
+    pub output_dir: PathBuf,
+    /// Frame numbers to save (starting at 1), or every frame if `None`
+    pub save_frames: Option<Vec<u64>>,
+    /// CSV file to write the draw cycle usage of every line to
+    pub cycle_report: Option<PathBuf>,
+    /// File to write the register writes made during the run to
+    pub record: Option<PathBuf>,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        HeadlessConfig {
+            frames: 1,
+            output_dir: PathBuf::from("vdpemu/frames"),
+            save_frames: None,
+            cycle_report: None,
+            record: None,
+        }
+    }
+}
+
+impl HeadlessConfig {
+    /// Whether the given frame number should be written out
+    fn should_save(&self, frame: u64) -> bool {
+        self.save_frames
+            .as_ref()
+            .is_none_or(|frames| frames.contains(&frame))
+    }
+}
+
+/// A frame with lines that didn't fit in the draw budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overload {
+    /// Frame number, starting at 1
+    pub frame: u64,
+    /// Number of lines over budget
+    pub lines: usize,
+    /// The line that needed the most cycles
+    pub worst_line: usize,
+    /// Draw usage of the worst line
+    pub worst: LineStats,
+    /// Cycles each line had
+    pub budget: u32,
+}
+
+/// What a headless run did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Paths of the PNGs written
+    pub written: Vec<PathBuf>,
+    /// Frames with lines over the draw budget
+    pub overloads: Vec<Overload>,
+}
+
+/// Render the configured number of frames, reporting the PNGs written and
+/// the frames that overran the draw budget
+pub fn run(vdp_state: &mut VdpState, config: &HeadlessConfig) -> Result<Report, image::ImageError> {
+    fs::create_dir_all(&config.output_dir)?;
+
+    let mut cycle_report = match &config.cycle_report {
+        Some(path) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            writeln!(
+                file,
+                "frame,line,sprites,tiles,cycles,budget,truncated_sprites"
+            )?;
+            Some(file)
+        }
+        None => None,
+    };
+
+    if config.record.is_some() {
+        vdp_state.recording = Some(CommandStream::default());
+    }
+
+    let mut report = Report::default();
+    for _ in 0..config.frames {
+        vdp_state.update_frame_buffer_data();
+
+        let frame = vdp_state.frame_count;
+        report.overloads.extend(overload(vdp_state));
+        if let Some(file) = &mut cycle_report {
+            let budget = vdp_state.timing.cycles_per_line;
+            for (line, stats) in vdp_state.line_stats.iter().enumerate() {
+                writeln!(
+                    file,
+                    "{frame},{line},{},{},{},{budget},{}",
+                    stats.sprites, stats.tiles, stats.cycles, stats.truncated_sprites
+                )?;
+            }
+        }
+
+        if config.should_save(frame) {
+            let path = config.output_dir.join(format!("frame_{frame:05}.png"));
+            save_png(vdp_state, &path)?;
+            report.written.push(path);
+        }
+    }
+
+    if let (Some(path), Some(recording)) = (&config.record, &vdp_state.recording) {
+        recording.save(path)?;
+    }
+
+    Ok(report)
+}
+
+/// The lines of the last frame that didn't fit in the draw budget, if any
+fn overload(vdp_state: &VdpState) -> Option<Overload> {
+    let lines = vdp_state
+        .line_stats
+        .iter()
+        .filter(|stats| stats.overloaded())
+        .count();
+    if lines == 0 {
+        return None;
+    }
+
+    let (worst_line, worst) = busiest_line(&vdp_state.line_stats)?;
+    Some(Overload {
+        frame: vdp_state.frame_count,
+        lines,
+        worst_line,
+        worst: *worst,
+        budget: vdp_state.timing.cycles_per_line,
+    })
+}
+
+/// Write the current frame buffer to a PNG file
+pub fn save_png(vdp_state: &VdpState, path: &Path) -> Result<(), image::ImageError> {
+    let image = RgbaImage::from_raw(
+        vdp_state.width,
+        vdp_state.height,
+        vdp_state.frame_buffer.clone(),
+    )
+    .expect("frame buffer matches the screen size");
+    image.save(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selected_frames_are_written_with_a_cycle_report() {
+        let dir = std::env::temp_dir().join(format!("vdpemu-headless-{}", std::process::id()));
+        let config = HeadlessConfig {
+            frames: 3,
+            output_dir: dir.clone(),
+            save_frames: Some(vec![2]),
+            cycle_report: Some(dir.join("cycles.csv")),
+            record: None,
+        };
+        let mut vdp = VdpState::new(32, 8);
+        vdp.palette[0] = 0x12_3456;
+
+        let report = run(&mut vdp, &config).unwrap();
+        assert_eq!(report.written, [dir.join("frame_00002.png")]);
+        assert!(report.overloads.is_empty());
+        let frame = image::open(&report.written[0]).unwrap().to_rgba8();
+        assert_eq!(frame.dimensions(), (32, 8));
+        assert_eq!(frame.get_pixel(31, 7).0, [0x12, 0x34, 0x56, 255]);
+
+        let report = fs::read_to_string(dir.join("cycles.csv")).unwrap();
+        assert_eq!(report.lines().count(), 1 + 3 * 8);
+        assert_eq!(report.lines().nth(1), Some("1,0,0,0,0,1360,0"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn frames_over_the_draw_budget_are_reported() {
+        let dir = std::env::temp_dir().join(format!("vdpemu-overload-{}", std::process::id()));
+        let config = HeadlessConfig {
+            frames: 2,
+            output_dir: dir.clone(),
+            save_frames: Some(Vec::new()),
+            ..HeadlessConfig::default()
+        };
+        let mut vdp = VdpState::new(64, 32);
+        let mut sprite = crate::sprite::Sprite::default();
+        sprite.y_height.set_height(1);
+        sprite.x_width.set_width(1);
+        vdp.sprites = vec![sprite; 3];
+        vdp.timing.cycles_per_line = 12;
+
+        // The doubled sprites cover the first 16 lines, and only one fits on each
+        let report = run(&mut vdp, &config).unwrap();
+        assert!(report.written.is_empty());
+        assert_eq!(report.overloads.len(), 2);
+        let overload = report.overloads[1];
+        assert_eq!((overload.frame, overload.lines), (2, 16));
+        assert_eq!(overload.worst.truncated_sprites, 2);
+        assert_eq!(overload.budget, 12);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
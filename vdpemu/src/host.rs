@@ -0,0 +1,342 @@
+//! Host register interface
+//!
+//! The VDP is programmed by a CPU through a small block of 16 bit registers.
+//! Neither the RTL nor any CPU implements this yet, so this is the reference
+//! for both. Offsets are in words:
+//!
+//! | Offset | Name          | Access | Description                                        |
+//! |--------|---------------|--------|----------------------------------------------------|
+//! | 0x0    | ADDRESS_LOW   | R/W    | VRAM word address bits 15:0                        |
+//! | 0x1    | ADDRESS_HIGH  | R/W    | VRAM word address bits 21:16                       |
+//! | 0x2    | INCREMENT     | R/W    | Added to the address after each DATA access        |
+//! | 0x3    | DATA          | R/W    | VRAM word at the address, then auto-increments     |
+//! | 0x4    | PALETTE_INDEX | R/W    | Palette entry (0-511) written by PALETTE_DATA      |
+//! | 0x5    | PALETTE_DATA  | W      | Two writes, `ggbb` then `00rr`, then next entry    |
+//! | 0x6    | SPRITE_INDEX  | R/W    | Sprite (0-511) written by SPRITE_DATA              |
+//! | 0x7    | SPRITE_DATA   | W      | Nine writes of the 144 bit record, low word first  |
+//! | 0x8    | STATUS        | R      | Bit 0: vblank, bit 1: line flag (cleared by reads) |
+//! | 0x9    | LINE          | R      | Line the raster is on                              |
+//! | 0xa    | LINE_COMPARE  | R/W    | Line that sets the line flag once drawn            |
+//!
+//! Writing PALETTE_INDEX or SPRITE_INDEX restarts a partially written entry.
+//! There is no separate tilemap metadata: a sprite's tilemap and tile bitmap
+//! addresses and tilemap size are part of its record, and the tilemaps and
+//! texels themselves are written to VRAM through DATA.
+
+use crate::sprite::Sprite;
+use crate::vdp::{VdpState, PALETTE_SIZE, SPRITE_COUNT};
+
+/// Number of 16 bit writes to SPRITE_DATA that make up a sprite record
+const SPRITE_RECORD_WORDS: usize = 9;
+/// Mask for the 22 bit VRAM word address
+const ADDRESS_MASK: u32 = 0x3f_ffff;
+
+/// STATUS bit set while the raster is outside the visible lines
+pub const STATUS_VBLANK: u16 = 1 << 0;
+/// STATUS bit set once the line in LINE_COMPARE has been drawn
+pub const STATUS_LINE: u16 = 1 << 1;
+
+/// The registers a host can access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    AddressLow,
+    AddressHigh,
+    Increment,
+    Data,
+    PaletteIndex,
+    PaletteData,
+    SpriteIndex,
+    SpriteData,
+    Status,
+    Line,
+    LineCompare,
+}
+
+impl Register {
+    pub const ALL: [Register; 11] = [
+        Register::AddressLow,
+        Register::AddressHigh,
+        Register::Increment,
+        Register::Data,
+        Register::PaletteIndex,
+        Register::PaletteData,
+        Register::SpriteIndex,
+        Register::SpriteData,
+        Register::Status,
+        Register::Line,
+        Register::LineCompare,
+    ];
+
+    /// The register at a word offset, if there is one
+    pub fn from_offset(offset: u8) -> Option<Self> {
+        Self::ALL.get(offset as usize).copied()
+    }
+
+    /// Word offset of the register
+    pub fn offset(self) -> u8 {
+        self as u8
+    }
+
+    /// Name of the register as used in the documentation
+    pub fn name(self) -> &'static str {
+        match self {
+            Register::AddressLow => "ADDRESS_LOW",
+            Register::AddressHigh => "ADDRESS_HIGH",
+            Register::Increment => "INCREMENT",
+            Register::Data => "DATA",
+            Register::PaletteIndex => "PALETTE_INDEX",
+            Register::PaletteData => "PALETTE_DATA",
+            Register::SpriteIndex => "SPRITE_INDEX",
+            Register::SpriteData => "SPRITE_DATA",
+            Register::Status => "STATUS",
+            Register::Line => "LINE",
+            Register::LineCompare => "LINE_COMPARE",
+        }
+    }
+
+    /// The register with the given name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|register| register.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Internal state of the host interface: address pointers and partially
+/// written palette entries and sprite records
+#[derive(Debug, Clone)]
+pub struct HostPort {
+    address: u32,
+    increment: u16,
+    palette_index: u16,
+    palette_low: Option<u16>,
+    sprite_index: u16,
+    sprite_words: Vec<u16>,
+    line_compare: u16,
+    line_flag: bool,
+}
+
+impl Default for HostPort {
+    fn default() -> Self {
+        HostPort {
+            address: 0,
+            increment: 1,
+            palette_index: 0,
+            palette_low: None,
+            sprite_index: 0,
+            sprite_words: Vec::with_capacity(SPRITE_RECORD_WORDS),
+            line_compare: 0,
+            line_flag: false,
+        }
+    }
+}
+
+impl HostPort {
+    /// Called by the VDP after each line, to raise the line flag
+    pub(crate) fn line_done(&mut self, line: u32) {
+        if line == self.line_compare as u32 {
+            self.line_flag = true;
+        }
+    }
+
+    fn advance_address(&mut self) {
+        self.address = (self.address + self.increment as u32) & ADDRESS_MASK;
+    }
+}
+
+impl VdpState {
+    /// Write a value to a register, as the host CPU would. Writes to read
+    /// only registers are ignored. Every write is added to the recording.
+    pub fn write_register(&mut self, register: Register, value: u16) {
+        self.record(register, value);
+
+        let host = &mut self.host;
+        match register {
+            Register::AddressLow => host.address = (host.address & !0xffff) | value as u32,
+            Register::AddressHigh => {
+                host.address = ((value as u32) << 16 | (host.address & 0xffff)) & ADDRESS_MASK
+            }
+            Register::Increment => host.increment = value,
+            Register::Data => {
+                let index = host.address as usize % self.vram.len();
+                self.vram[index] = value;
+                host.advance_address();
+            }
+            Register::PaletteIndex => {
+                host.palette_index = value % PALETTE_SIZE as u16;
+                host.palette_low = None;
+            }
+            Register::PaletteData => match host.palette_low.take() {
+                None => host.palette_low = Some(value),
+                Some(low) => {
+                    self.palette[host.palette_index as usize] =
+                        ((value as u32 & 0xff) << 16) | low as u32;
+                    host.palette_index = (host.palette_index + 1) % PALETTE_SIZE as u16;
+                }
+            },
+            Register::SpriteIndex => {
+                host.sprite_index = value % SPRITE_COUNT as u16;
+                host.sprite_words.clear();
+            }
+            Register::SpriteData => {
+                host.sprite_words.push(value);
+                if host.sprite_words.len() == SPRITE_RECORD_WORDS {
+                    let words = std::mem::take(&mut host.sprite_words);
+                    let bit = |n: usize| ((words[n / 16] >> (n % 16)) & 1) as u64;
+                    let fields: Vec<u64> = (0..4)
+                        .map(|field| (0..36).fold(0, |acc, b| acc | bit(field * 36 + b) << b))
+                        .collect();
+                    let index = host.sprite_index as usize;
+                    if index >= self.sprites.len() {
+                        self.sprites.resize(index + 1, Sprite::default());
+                    }
+                    self.sprites[index] = Sprite::from_record(&fields);
+                    host.sprite_index = (host.sprite_index + 1) % SPRITE_COUNT as u16;
+                }
+            }
+            Register::LineCompare => host.line_compare = value,
+            Register::Status | Register::Line => {}
+        }
+    }
+
+    /// Read a register, as the host CPU would. Reading STATUS clears the line
+    /// flag and reading DATA advances the address. Write only registers read
+    /// as zero.
+    pub fn read_register(&mut self, register: Register) -> u16 {
+        let host = &mut self.host;
+        match register {
+            Register::AddressLow => host.address as u16,
+            Register::AddressHigh => (host.address >> 16) as u16,
+            Register::Increment => host.increment,
+            Register::Data => {
+                let value = self.vram[host.address as usize % self.vram.len()];
+                host.advance_address();
+                value
+            }
+            Register::PaletteIndex => host.palette_index,
+            Register::SpriteIndex => host.sprite_index,
+            Register::Status => {
+                let mut status = 0;
+                if self.line >= self.height {
+                    status |= STATUS_VBLANK;
+                }
+                if std::mem::take(&mut host.line_flag) {
+                    status |= STATUS_LINE;
+                }
+                status
+            }
+            Register::Line => self.line as u16,
+            Register::LineCompare => host.line_compare,
+            Register::PaletteData | Register::SpriteData => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vdp() -> VdpState {
+        VdpState::new(64, 16)
+    }
+
+    #[test]
+    fn data_writes_advance_by_the_increment() {
+        let mut vdp = vdp();
+        vdp.write_register(Register::AddressHigh, 0x1);
+        vdp.write_register(Register::AddressLow, 0xfffe);
+        vdp.write_register(Register::Increment, 2);
+        for value in [0xaaaa, 0xbbbb] {
+            vdp.write_register(Register::Data, value);
+        }
+        assert_eq!(vdp.vram[0x1_fffe], 0xaaaa);
+        assert_eq!(vdp.vram[0x2_0000], 0xbbbb);
+        assert_eq!(vdp.read_register(Register::AddressHigh), 0x2);
+        assert_eq!(vdp.read_register(Register::AddressLow), 0x2);
+
+        vdp.write_register(Register::AddressLow, 0);
+        vdp.write_register(Register::AddressHigh, 0x2);
+        assert_eq!(vdp.read_register(Register::Data), 0xbbbb);
+        assert_eq!(vdp.read_register(Register::AddressLow), 2);
+    }
+
+    #[test]
+    fn addresses_wrap_at_22_bits() {
+        let mut vdp = vdp();
+        vdp.write_register(Register::AddressHigh, 0xffff);
+        vdp.write_register(Register::AddressLow, 0xffff);
+        assert_eq!(vdp.read_register(Register::AddressHigh), 0x3f);
+        vdp.write_register(Register::Data, 1);
+        assert_eq!(vdp.read_register(Register::AddressHigh), 0);
+        assert_eq!(vdp.read_register(Register::AddressLow), 0);
+    }
+
+    #[test]
+    fn palette_entries_take_two_writes() {
+        let mut vdp = vdp();
+        vdp.write_register(Register::PaletteIndex, 0x1ff);
+        vdp.write_register(Register::PaletteData, 0x3456);
+        vdp.write_register(Register::PaletteData, 0x0012);
+        assert_eq!(vdp.palette[0x1ff], 0x12_3456);
+        assert_eq!(vdp.read_register(Register::PaletteIndex), 0);
+
+        // Writing the index drops the half written entry
+        vdp.write_register(Register::PaletteData, 0x1111);
+        vdp.write_register(Register::PaletteIndex, 4);
+        vdp.write_register(Register::PaletteData, 0x5678);
+        vdp.write_register(Register::PaletteData, 0x00ab);
+        assert_eq!(vdp.palette[0], 0);
+        assert_eq!(vdp.palette[4], 0xab_5678);
+    }
+
+    #[test]
+    fn sprite_records_take_nine_writes_low_word_first() {
+        let fields = [0x1_2345_6789, 0xa_bcde_f012, 0x3_4567_89ab, 0xc_def0_1234];
+        let record: u128 = fields
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, &field)| acc | (field as u128) << (36 * i));
+        let high = 0xcdefu16; // bits 143:128
+
+        let mut vdp = vdp();
+        vdp.write_register(Register::SpriteIndex, 3);
+        for word in 0..8 {
+            vdp.write_register(Register::SpriteData, (record >> (16 * word)) as u16);
+        }
+        assert!(vdp.sprites.len() <= 3);
+        vdp.write_register(Register::SpriteData, high);
+
+        assert_eq!(vdp.sprites[3], Sprite::from_record(&fields));
+        assert_eq!(vdp.read_register(Register::SpriteIndex), 4);
+    }
+
+    #[test]
+    fn reading_status_clears_the_line_flag() {
+        let mut vdp = vdp();
+        vdp.write_register(Register::LineCompare, 2);
+        for _ in 0..3 {
+            vdp.step_line();
+        }
+        assert_eq!(vdp.read_register(Register::Status), STATUS_LINE);
+        assert_eq!(vdp.read_register(Register::Status), 0);
+
+        while vdp.line < vdp.height {
+            vdp.step_line();
+        }
+        assert_eq!(vdp.read_register(Register::Status), STATUS_VBLANK);
+        assert_eq!(vdp.read_register(Register::Line), vdp.height as u16);
+    }
+
+    #[test]
+    fn registers_are_found_by_name_and_offset() {
+        for register in Register::ALL {
+            assert_eq!(Register::from_offset(register.offset()), Some(register));
+            assert_eq!(Register::from_name(register.name()), Some(register));
+        }
+        assert_eq!(
+            Register::from_name("sprite_data"),
+            Some(Register::SpriteData)
+        );
+        assert_eq!(Register::from_offset(11), None);
+    }
+}
@@ -0,0 +1,11 @@
+//! Scanline emulator of the VDP
+//!
+//! The emulator renders the same asset files as the Verilator sim, and can
+//! be driven by a host through the register interface in [`host`].
+
+pub mod headless;
+pub mod host;
+pub mod sprite;
+pub mod stream;
+pub mod timing;
+pub mod vdp;
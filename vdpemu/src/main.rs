@@ -1,114 +1,198 @@
-use bitfield::bitfield;
 use macroquad::prelude::*;
 
+use vdpemu::headless::{self, HeadlessConfig};
+use vdpemu::stream::{CommandStream, Player};
+use vdpemu::timing::{busiest_line, DrawTiming};
+use vdpemu::vdp::{VdpState, SCREEN_HEIGHT, SCREEN_WIDTH, VELOCITY_DELAY_FRAMES};
+
 // --- Configuration ---
 // Dimensions of the emulated VDP screen
-const VDP_WIDTH: u32 = 960;
-const VDP_HEIGHT: u32 = 540;
+const VDP_WIDTH: u32 = SCREEN_WIDTH;
+const VDP_HEIGHT: u32 = SCREEN_HEIGHT;
 
 // How much to scale the VDP output on the main window
-const SCREEN_SCALE: f32 = 2.0;
+const SCREEN_SCALE: f32 = 1.0;
 
 const WINDOW_WIDTH: u32 = (VDP_WIDTH as f32 * SCREEN_SCALE) as u32;
 const WINDOW_HEIGHT: u32 = (VDP_HEIGHT as f32 * SCREEN_SCALE) as u32;
 
-// tilemaps must fit into a 2K-word page
-
-bitfield! {
-    struct TilemapMetadata(u64);
-    impl Debug;
-    u32;
-
-    /// Width in tiles, as a power of 2
-    width, set_width: 2, 0;
-
-    /// Address of the page containing the tilemap in VRAM
-    /// This must be 2048-word (4KB) aligned, so this value is shifted left by
-    /// 11 bits to get the (word) address.
-    tilemap_address, set_tilemap_address: 15, 3;
-
-    /// Extra non-displayed width expressed as a power of 2
-    /// For example if size is 6 indicating a 64x64 tilemap, and extra_stride is 4 (16),
-    /// then the tilemap will be effectively 80x64 with only the leftmost 64x64 being displayed.
-    /// The formula is index = y << width + y << extra_stride + x
-    /// This is useful for a 80-column text mode, where a 64x32 and a few 16x16 sprites can be
-    /// used to display 80x32 characters.
-    extra_stride, set_extra_stride: 18, 16;
-
-    /// Address of the start of the texture in VRAM
-    /// This must be 2048-word (4KB) aligned, so this value is shifted left by
-    /// 11 bits to get the (word) address.
-    texture_address, set_texture_address: 31, 19;
-
+/// Command line options: the asset files to load (the same ones the
+/// Verilator sim uses) and whether to run without a window
+struct Options {
+    palette_hex: String,
+    tiles_hex: String,
+    tilemap_hex: String,
+    /// Binary asset file to load instead of the palette, tiles and tilemap hex files
+    assets_bin: Option<String>,
+    sprites_hex: Option<String>,
+    play: Option<String>,
+    timing: DrawTiming,
+    velocity_delay_frames: u64,
+    /// Mirror sprites with x_flip set, ahead of the RTL
+    future_x_flip: bool,
+    headless: Option<HeadlessConfig>,
+}
 
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            palette_hex: "rtl/palette.hex".to_string(),
+            tiles_hex: "rtl/tiles.hex".to_string(),
+            tilemap_hex: "rtl/tile_map.hex".to_string(),
+            assets_bin: None,
+            sprites_hex: Some("rtl/sprites.hex".to_string()),
+            play: None,
+            timing: DrawTiming::default(),
+            velocity_delay_frames: VELOCITY_DELAY_FRAMES,
+            future_x_flip: false,
+            headless: None,
+        }
+    }
 }
 
-struct Sprite {
-    y: u16,
-    height: u8,
-    y_flip: bool,
+impl Options {
+    /// Parse the command line, returning `None` if the program should exit
+    fn from_args() -> Result<Option<Self>, String> {
+        let args: Vec<String> = std::env::args().collect();
+        let mut options = Options::default();
+        let mut headless = HeadlessConfig::default();
+        let mut i = 1;
 
-    x: u16,
-    width: u8,
-    x_flip: bool,
-}
+        while i < args.len() {
+            let arg = args[i].as_str();
+            let mut value = || {
+                i += 1;
+                args.get(i)
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for {arg}"))
+            };
 
-// --- Simple VDP State Simulation ---
-struct VdpState {
-    width: u32,
-    height: u32,
-    /// Represents the VDP's video memory or generated output for a frame
-    frame_buffer: Vec<u8>,
-    frame_count: u64, // To add some simple animation
-
-    vram: Vec<u8>,
-    tilemaps: Vec<TilemapMetadata>,
-    sprites: Vec<Sprite>,
-}
+            match arg {
+                "--palette" => options.palette_hex = value()?,
+                "--tiles" => options.tiles_hex = value()?,
+                "--tilemap" => options.tilemap_hex = value()?,
+                "--assets" => options.assets_bin = Some(value()?),
+                "--sprites" => options.sprites_hex = Some(value()?),
+                "--no-sprites" => options.sprites_hex = None,
+                "--play" => options.play = Some(value()?),
+                "--cycles-per-line" => options.timing.cycles_per_line = parse(arg, &value()?)?,
+                "--memory-latency" => options.timing.memory_latency = parse(arg, &value()?)?,
+                "--velocity-delay" => options.velocity_delay_frames = parse(arg, &value()?)?,
+                "--future-x-flip" => options.future_x_flip = true,
+                "--headless" => options.headless = Some(HeadlessConfig::default()),
+                "--frames" => headless.frames = parse(arg, &value()?)?,
+                "--output-dir" => headless.output_dir = value()?.into(),
+                "--cycle-report" => headless.cycle_report = Some(value()?.into()),
+                "--record" => headless.record = Some(value()?.into()),
+                "--save" => {
+                    let frames = value()?;
+                    let frames = frames
+                        .split(',')
+                        .map(|frame| frame.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| format!("Invalid frame list {frames}: {e}"))?;
+                    headless.save_frames = Some(frames);
+                }
+                "--help" => {
+                    println!("VDP Emulator - Renders VDP asset files like the RTL does");
+                    println!();
+                    println!("Usage:");
+                    println!("  vdpemu [options]");
+                    println!();
+                    println!("Options:");
+                    println!(
+                        "  --palette FILE         Palette hex file (default: rtl/palette.hex)"
+                    );
+                    println!("  --tiles FILE           Tiles hex file (default: rtl/tiles.hex)");
+                    println!(
+                        "  --tilemap FILE         Tilemap hex file (default: rtl/tile_map.hex)"
+                    );
+                    println!("  --assets FILE          Binary asset file from imgconv --bin, instead of the three files above");
+                    println!(
+                        "  --sprites FILE         Sprites hex file (default: rtl/sprites.hex)"
+                    );
+                    println!("  --no-sprites           Start with an empty sprite list");
+                    println!(
+                        "  --play FILE            Replay the register writes in a command stream"
+                    );
+                    println!("  --cycles-per-line NUM  Draw clocks per scan line (default: 1360)");
+                    println!(
+                        "  --memory-latency NUM   Extra draw clocks per tile fetch (default: 0)"
+                    );
+                    println!("  --velocity-delay NUM   Frames before sprites start moving (default: 45, 480 on the FPGA)");
+                    println!("  --future-x-flip        Mirror sprites with x_flip set, which the RTL doesn't do yet");
+                    println!(
+                        "  --headless             Render to PNG files without opening a window"
+                    );
+                    println!(
+                        "  --frames NUM           Number of frames to render headless (default: 1)"
+                    );
+                    println!("  --output-dir DIR       Directory for headless PNGs (default: vdpemu/frames)");
+                    println!(
+                        "  --save N,N,...         Frame numbers to save, from 1 (default: all)"
+                    );
+                    println!("  --cycle-report FILE    Write per line draw cycle usage as CSV");
+                    println!(
+                        "  --record FILE          Write the register writes made while headless"
+                    );
+                    println!("  --help                 Show this help message");
+                    return Ok(None);
+                }
+                _ => return Err(format!("Unknown option: {arg}")),
+            }
+            i += 1;
+        }
 
-impl VdpState {
-    fn new(width: u32, height: u32) -> Self {
-        VdpState {
-            width,
-            height,
-            // Initialize with black pixels
-            frame_buffer: vec![0; (width * height * 4) as usize],
-            frame_count: 0,
-
-            vram: vec![0; 8 * 1024 * 1024], // 8MB of VRAM
-            tilemaps: Vec::new(),
-            sprites: Vec::new(),
+        if options.headless.is_some() {
+            options.headless = Some(headless);
         }
+        Ok(Some(options))
     }
 
-    fn update_frame_buffer_data(&mut self) {
-        self.frame_count += 1;
-        let frame_count = self.frame_count;
-        for y in 0..self.height {
-            let row_offset = y * (self.width << 2);
-            for x in 0..self.width {
-                let index = (row_offset + (x << 2)) as usize;
-                // Simple pattern based on coordinates and time
-                let r = (((x + frame_count as u32) >> 1) & 0xff) as u8;
-                let g = (((y + frame_count as u32) >> 2) & 0xff) as u8;
-                let b = ((x + y + frame_count as u32) & 0xff) as u8;
-                self.frame_buffer[index] = r;
-                self.frame_buffer[index + 1] = g;
-                self.frame_buffer[index + 2] = b;
-                self.frame_buffer[index + 3] = 255;
-            }
+    /// Load the asset files and timing settings into the VDP
+    fn load(&self, vdp_state: &mut VdpState) -> std::io::Result<()> {
+        vdp_state.timing = self.timing;
+        vdp_state.velocity_delay_frames = self.velocity_delay_frames;
+        vdp_state.future_x_flip = self.future_x_flip;
+        if let Some(assets_bin) = &self.assets_bin {
+            vdp_state.load_assets_bin(assets_bin)?;
+        } else {
+            vdp_state.load_palette_hex(&self.palette_hex)?;
+            vdp_state.load_tiles_hex(&self.tiles_hex)?;
+            vdp_state.load_tilemap_hex(&self.tilemap_hex)?;
+        }
+        if let Some(sprites_hex) = &self.sprites_hex {
+            vdp_state.load_sprites_hex(sprites_hex)?;
         }
+        if let Some(play) = &self.play {
+            vdp_state.playback = Some(Player::new(CommandStream::load(play)?));
+        }
+        Ok(())
     }
+}
 
-    fn draw_to_texture(&self, texture: &mut Texture2D) {
-        texture.update_from_bytes(self.width, self.height, &self.frame_buffer);
-    }
+/// Parse a numeric option value
+fn parse<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value {value} for {option}: {e}"))
+}
 
-    fn texture(&self) -> Texture2D {
-        let tex = Texture2D::from_rgba8(self.width as u16, self.height as u16, &self.frame_buffer);
-        tex.set_filter(FilterMode::Nearest);
-        tex
-    }
+fn draw_to_texture(vdp_state: &VdpState, texture: &mut Texture2D) {
+    texture.update_from_bytes(vdp_state.width, vdp_state.height, &vdp_state.frame_buffer);
+}
+
+fn texture(vdp_state: &VdpState) -> Texture2D {
+    let tex = Texture2D::from_rgba8(
+        vdp_state.width as u16,
+        vdp_state.height as u16,
+        &vdp_state.frame_buffer,
+    );
+    tex.set_filter(FilterMode::Nearest);
+    tex
 }
 
 fn window_conf() -> Conf {
@@ -121,17 +205,61 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
+fn main() {
+    let options = match Options::from_args() {
+        Ok(Some(options)) => options,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!("Use --help for usage information.");
+            std::process::exit(1);
+        }
+    };
+
     // Initialize our simulated VDP state
     let mut vdp_state = VdpState::new(VDP_WIDTH, VDP_HEIGHT);
+    if let Err(e) = options.load(&mut vdp_state) {
+        eprintln!("Failed to load assets: {e}");
+        std::process::exit(1);
+    }
+
+    match &options.headless {
+        Some(config) => match headless::run(&mut vdp_state, config) {
+            Ok(report) => {
+                for overload in &report.overloads {
+                    println!(
+                        "Frame {}: {} lines over budget, worst is line {} needing {} of {} cycles ({} sprites truncated)",
+                        overload.frame,
+                        overload.lines,
+                        overload.worst_line,
+                        overload.worst.cycles,
+                        overload.budget,
+                        overload.worst.truncated_sprites
+                    );
+                }
+                println!(
+                    "Wrote {} frames to {}",
+                    report.written.len(),
+                    config.output_dir.display()
+                );
+            }
+            Err(e) => {
+                eprintln!("Failed to write frames: {e}");
+                std::process::exit(1);
+            }
+        },
+        None => macroquad::Window::from_config(window_conf(), run_window(vdp_state)),
+    }
+}
 
+/// Show the emulated screen in a window, rendering a new frame each refresh
+async fn run_window(mut vdp_state: VdpState) {
     // let image = Image::gen_image_color(WINDOW_WIDTH as u16, WINDOW_HEIGHT as u16, BLACK);
-    let mut texture = vdp_state.texture();
+    let mut texture = texture(&vdp_state);
 
     loop {
         vdp_state.update_frame_buffer_data();
-        vdp_state.draw_to_texture(&mut texture);
+        draw_to_texture(&vdp_state, &mut texture);
 
         // Draw the texture, scaling it up
         draw_texture_ex(
@@ -150,6 +278,19 @@ async fn main() {
         );
 
         draw_text(&format!("FPS: {}", get_fps()), 10.0, 40.0, 20.0, YELLOW);
+        if let Some((line, stats)) = busiest_line(&vdp_state.line_stats) {
+            let colour = if stats.overloaded() { RED } else { YELLOW };
+            draw_text(
+                &format!(
+                    "Busiest line {line}: {}/{} cycles",
+                    stats.cycles, vdp_state.timing.cycles_per_line
+                ),
+                10.0,
+                60.0,
+                20.0,
+                colour,
+            );
+        }
 
         // Advance to the next frame
         next_frame().await
@@ -0,0 +1,163 @@
+//! Sprite attributes, bit for bit the packed structs in rtl/sprite_types.sv
+//!
+//! Each sprite BRAM entry is 144 bits: four 36 bit structs, with
+//! `sprite_y_height_t` in the least significant bits.
+
+use bitfield::bitfield;
+
+bitfield! {
+    /// `sprite_y_height_t`
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
+    pub struct SpriteYHeight(u64);
+    impl Debug;
+    u32;
+
+    /// Fractional part of the screen y coordinate, in 1/16ths of a pixel
+    pub screen_sub_y, set_screen_sub_y: 3, 0;
+    /// Screen y coordinate of the top of the sprite (12 bit, two's complement)
+    pub screen_y, set_screen_y: 15, 4;
+    /// Row of the tilemap shown at the top of the sprite
+    pub tilemap_y, set_tilemap_y: 23, 16;
+    /// Height in tiles
+    pub height, set_height: 31, 24;
+    /// The tilemap is (1 << (a + 4)) + (1 << (b + 4)) tiles wide
+    pub tilemap_size_b, set_tilemap_size_b: 33, 32;
+    pub tilemap_size_a, set_tilemap_size_a: 35, 34;
+}
+
+bitfield! {
+    /// `sprite_x_width_t`
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
+    pub struct SpriteXWidth(u64);
+    impl Debug;
+    u32;
+
+    /// Fractional part of the screen x coordinate, in 1/16ths of a pixel
+    pub screen_sub_x, set_screen_sub_x: 3, 0;
+    /// Screen x coordinate of the left of the sprite (12 bit, wraps around
+    /// the line buffer)
+    pub screen_x, set_screen_x: 15, 4;
+    /// Column of the tilemap shown at the left of the sprite
+    pub tilemap_x, set_tilemap_x: 23, 16;
+    /// Width in tiles
+    pub width, set_width: 31, 24;
+    pub x_flip, set_x_flip: 32;
+    pub y_flip, set_y_flip: 33;
+}
+
+bitfield! {
+    /// `sprite_addr_t`
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
+    pub struct SpriteAddr(u64);
+    impl Debug;
+    u32;
+
+    /// Word address of the tile bitmap (texels)
+    pub tile_bitmap_addr, set_tile_bitmap_addr: 17, 0;
+    /// Address of the tilemap in 512 word units
+    pub tilemap_addr, set_tilemap_addr: 35, 18;
+}
+
+bitfield! {
+    /// `sprite_velocity_t`, in 1/16ths of a pixel per frame
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
+    pub struct SpriteVelocity(u64);
+    impl Debug;
+    u32;
+
+    pub y_velocity, set_y_velocity: 17, 0;
+    pub x_velocity, set_x_velocity: 35, 18;
+}
+
+/// One entry of the sprite BRAM
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sprite {
+    pub y_height: SpriteYHeight,
+    pub x_width: SpriteXWidth,
+    pub addr: SpriteAddr,
+    /// Applied by the sprite matcher once per frame, see `apply_velocity`
+    pub velocity: SpriteVelocity,
+}
+
+impl Sprite {
+    /// Decode a sprite BRAM record, given as 36 bit fields starting from the
+    /// least significant. Missing fields (such as the velocity of a 108 bit
+    /// record) are zero.
+    pub fn from_record(fields: &[u64]) -> Self {
+        let field = |i: usize| fields.get(i).copied().unwrap_or(0) & 0xf_ffff_ffff;
+        Sprite {
+            y_height: SpriteYHeight(field(0)),
+            x_width: SpriteXWidth(field(1)),
+            addr: SpriteAddr(field(2)),
+            velocity: SpriteVelocity(field(3)),
+        }
+    }
+
+    /// Move the sprite by its velocity, like the sprite matcher does during
+    /// vblank. Only the low 16 bits of each velocity are added to the 12.4
+    /// fixed point position, and the top bit of the result is cleared, so
+    /// positions wrap around at 2048 pixels.
+    pub fn apply_velocity(&mut self) {
+        let y = add_velocity(
+            self.y_height.screen_y(),
+            self.y_height.screen_sub_y(),
+            self.velocity.y_velocity(),
+        );
+        self.y_height.set_screen_y(y >> 4);
+        self.y_height.set_screen_sub_y(y & 0xf);
+
+        let x = add_velocity(
+            self.x_width.screen_x(),
+            self.x_width.screen_sub_x(),
+            self.velocity.x_velocity(),
+        );
+        self.x_width.set_screen_x(x >> 4);
+        self.x_width.set_screen_sub_x(x & 0xf);
+    }
+}
+
+/// `{1'b0, ({screen, sub} + velocity[15:0])[14:0]}`
+fn add_velocity(screen: u32, sub: u32, velocity: u32) -> u32 {
+    (((screen << 4) | sub) + velocity) & 0x7fff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn velocity_moves_the_fixed_point_position() {
+        // 10.5 pixels plus 1.75 pixels
+        assert_eq!(add_velocity(10, 8, 28), (12 << 4) | 4);
+        // -1/16th of a pixel, as an 18 bit velocity
+        assert_eq!(add_velocity(10, 0, 0x3ffff), (9 << 4) | 0xf);
+    }
+
+    #[test]
+    fn positions_wrap_at_2048_pixels() {
+        assert_eq!(add_velocity(0x7ff, 0xf, 1), 0);
+        assert_eq!(add_velocity(0, 0, 0x3ffff), 0x7fff);
+        // the top bit of the 12 bit screen coordinate is cleared
+        assert_eq!(add_velocity(0xfff, 0, 0) >> 4, 0x7ff);
+    }
+
+    #[test]
+    fn applying_velocity_updates_both_coordinates() {
+        let mut sprite = Sprite::default();
+        sprite.y_height.set_screen_y(100);
+        sprite.x_width.set_screen_x(0x7ff);
+        sprite.x_width.set_screen_sub_x(0xf);
+        sprite.velocity.set_y_velocity(0x3fff0);
+        sprite.velocity.set_x_velocity(0x11);
+
+        sprite.apply_velocity();
+        assert_eq!(
+            (sprite.y_height.screen_y(), sprite.y_height.screen_sub_y()),
+            (99, 0)
+        );
+        assert_eq!(
+            (sprite.x_width.screen_x(), sprite.x_width.screen_sub_x()),
+            (1, 0)
+        );
+    }
+}
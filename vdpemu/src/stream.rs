@@ -0,0 +1,247 @@
+//! Recording and playback of timestamped register writes
+//!
+//! A command stream is a text file with one register write per line:
+//!
+//! ```text
+//! # frame line register value
+//! 1 0 PALETTE_INDEX 0x010
+//! 1 0 PALETTE_DATA 0x3456
+//! 1 0 PALETTE_DATA 0x0012
+//! 46 720 SPRITE_INDEX 2
+//! ```
+//!
+//! Frames count from 1 like the headless PNGs, and lines count into vertical
+//! blanking. Writes are applied just before their line is drawn, in file
+//! order. Registers are given by name (see [`crate::host`]) or word offset,
+//! and values in decimal or `0x` hex. `#` starts a comment.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::host::Register;
+
+/// A register write and when it happens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    pub frame: u64,
+    pub line: u32,
+    pub register: Register,
+    pub value: u16,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {:#06x}",
+            self.frame,
+            self.line,
+            self.register.name(),
+            self.value
+        )
+    }
+}
+
+/// A sequence of register writes in the order they happen
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandStream {
+    pub commands: Vec<Command>,
+}
+
+impl CommandStream {
+    /// Parse the text format, checking the writes are in time order
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut commands: Vec<Command> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let [frame, raster_line, register, value] = tokens[..] else {
+                return Err(invalid_data(
+                    line_number,
+                    "expected frame, line, register and value",
+                ));
+            };
+
+            let command = Command {
+                frame: parse_number(frame)
+                    .ok_or_else(|| invalid_data(line_number, format!("bad frame {frame}")))?,
+                line: parse_number(raster_line)
+                    .and_then(|line| u32::try_from(line).ok())
+                    .ok_or_else(|| invalid_data(line_number, format!("bad line {raster_line}")))?,
+                register: parse_register(register).ok_or_else(|| {
+                    invalid_data(line_number, format!("unknown register {register}"))
+                })?,
+                value: parse_number(value)
+                    .and_then(|value| u16::try_from(value).ok())
+                    .ok_or_else(|| invalid_data(line_number, format!("bad value {value}")))?,
+            };
+
+            if let Some(last) = commands.last() {
+                if (command.frame, command.line) < (last.frame, last.line) {
+                    return Err(invalid_data(
+                        line_number,
+                        "write is earlier than the last one",
+                    ));
+                }
+            }
+            commands.push(command);
+        }
+
+        Ok(CommandStream { commands })
+    }
+
+    /// Read a command stream file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+        Self::parse(&text).map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+    }
+
+    /// Write the stream in the text format
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for CommandStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# frame line register value")?;
+        for command in &self.commands {
+            writeln!(f, "{command}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Plays a command stream back into a VDP as the raster reaches each write
+#[derive(Debug, Clone, Default)]
+pub struct Player {
+    stream: CommandStream,
+    next: usize,
+}
+
+impl Player {
+    pub fn new(stream: CommandStream) -> Self {
+        Player { stream, next: 0 }
+    }
+
+    /// Take the writes due at or before the given frame and line
+    pub fn due(&mut self, frame: u64, line: u32) -> &[Command] {
+        let start = self.next;
+        while let Some(command) = self.stream.commands.get(self.next) {
+            if (command.frame, command.line) > (frame, line) {
+                break;
+            }
+            self.next += 1;
+        }
+        &self.stream.commands[start..self.next]
+    }
+
+    /// Whether every write has been played
+    pub fn finished(&self) -> bool {
+        self.next >= self.stream.commands.len()
+    }
+}
+
+/// Parse a decimal or `0x` prefixed hex number
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parse a register name or word offset
+fn parse_register(text: &str) -> Option<Register> {
+    Register::from_name(text).or_else(|| {
+        parse_number(text)
+            .and_then(|offset| u8::try_from(offset).ok())
+            .and_then(Register::from_offset)
+    })
+}
+
+fn invalid_data(line: usize, error: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {line}: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "\
+# frame line register value
+1 0 PALETTE_INDEX 0x010
+1 0 palette_data 13398 # decimal
+46 720 6 2
+";
+
+    #[test]
+    fn streams_parse_names_offsets_and_numbers() {
+        let stream = CommandStream::parse(TEXT).unwrap();
+        assert_eq!(
+            stream.commands,
+            [
+                Command {
+                    frame: 1,
+                    line: 0,
+                    register: Register::PaletteIndex,
+                    value: 0x10
+                },
+                Command {
+                    frame: 1,
+                    line: 0,
+                    register: Register::PaletteData,
+                    value: 0x3456
+                },
+                Command {
+                    frame: 46,
+                    line: 720,
+                    register: Register::SpriteIndex,
+                    value: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_read_back_as_written() {
+        let stream = CommandStream::parse(TEXT).unwrap();
+        assert_eq!(CommandStream::parse(&stream.to_string()).unwrap(), stream);
+    }
+
+    #[test]
+    fn bad_writes_name_their_line() {
+        let error = |text: &str| CommandStream::parse(text).unwrap_err().to_string();
+        assert_eq!(
+            error("1 0 DATA"),
+            "line 1: expected frame, line, register and value"
+        );
+        assert_eq!(error("\n1 0 COLOR 1"), "line 2: unknown register COLOR");
+        assert_eq!(error("1 0 DATA 0x10000"), "line 1: bad value 0x10000");
+        assert_eq!(error("1 0 11 1"), "line 1: unknown register 11");
+        assert_eq!(
+            error("2 0 DATA 1\n1 5 DATA 2"),
+            "line 2: write is earlier than the last one"
+        );
+    }
+
+    #[test]
+    fn players_hand_out_writes_as_the_raster_reaches_them() {
+        let mut player = Player::new(CommandStream::parse(TEXT).unwrap());
+        assert_eq!(player.due(0, 700).len(), 0);
+        assert_eq!(player.due(1, 0).len(), 2);
+        assert_eq!(player.due(46, 719).len(), 0);
+        assert!(!player.finished());
+        assert_eq!(player.due(50, 0)[0].register, Register::SpriteIndex);
+        assert!(player.finished());
+    }
+}
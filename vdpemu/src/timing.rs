@@ -0,0 +1,72 @@
+//! Approximate cycle model of the draw domain
+//!
+//! The draw domain has a fixed number of clocks to fill the off-screen line
+//! buffer before it gets flipped. Anything the sprite controller hasn't
+//! finished drawing by then is silently lost, which is what this models.
+
+/// Cycle costs of the draw pipeline, in draw clocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrawTiming {
+    /// Draw clocks available per scan line, including blanking
+    pub cycles_per_line: u32,
+    /// Overhead per sprite: sprite_controller.sv spends a cycle in LOADING
+    /// waiting for `sprite_ready` and a cycle in LOADED latching `lb_addr`
+    /// and `sprite_width`, before the `2 * width` DRAWING cycles that each
+    /// fetch 4 texels
+    pub cycles_per_sprite: u32,
+    /// Extra cycles per tile waiting on memory for the tilemap entry and
+    /// texels. Zero while they live in block RAM, but SDRAM will add latency.
+    pub memory_latency: u32,
+    /// Cycles between the sprite controller issuing a 4 texel fetch and the
+    /// doubled pixels reaching the line buffer (d3 to d7)
+    pub pipeline_latency: u32,
+}
+
+impl Default for DrawTiming {
+    fn default() -> Self {
+        DrawTiming {
+            cycles_per_line: 1360,
+            // LOADING + LOADED, see the field
+            cycles_per_sprite: 2,
+            memory_latency: 0,
+            pipeline_latency: 5,
+        }
+    }
+}
+
+impl DrawTiming {
+    /// Whether a 4 texel fetch issued at `cycle` reaches the line buffer
+    /// before it is flipped
+    pub fn fits(&self, cycle: u32) -> bool {
+        cycle.saturating_add(self.pipeline_latency) < self.cycles_per_line
+    }
+}
+
+/// Draw domain usage for a single scan line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineStats {
+    /// Sprites intersecting the line
+    pub sprites: u32,
+    /// Tiles those sprites cover
+    pub tiles: u32,
+    /// Cycles needed to draw everything, which may be more than the budget
+    pub cycles: u32,
+    /// Sprites that were cut short or not drawn at all
+    pub truncated_sprites: u32,
+}
+
+impl LineStats {
+    /// Whether the line needed more cycles than it had
+    pub fn overloaded(&self) -> bool {
+        self.truncated_sprites > 0
+    }
+}
+
+/// The first line that needed the most cycles, and its index
+pub fn busiest_line(line_stats: &[LineStats]) -> Option<(usize, &LineStats)> {
+    line_stats
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|(_, stats)| stats.cycles)
+}
@@ -0,0 +1,612 @@
+//! Scanline model of the VDP
+//!
+//! This mirrors the draw domain of rtl/vdp.sv: for each scan line the sprite
+//! list is walked, the tilemap entries and 4bpp texels of every sprite that
+//! intersects the line are fetched from VRAM, doubled, and written into a line
+//! buffer, which is then sent through the palette to the frame buffer.
+
+use std::io;
+use std::path::Path;
+
+use imgconv::{binfile, hexfile, ConversionError};
+
+use crate::host::{HostPort, Register};
+use crate::sprite::Sprite;
+use crate::stream::{Command, CommandStream, Player};
+use crate::timing::{DrawTiming, LineStats};
+
+/// Width of the visible screen in pixels (720p, like the RTL)
+pub const SCREEN_WIDTH: u32 = 1280;
+/// Height of the visible screen in pixels
+pub const SCREEN_HEIGHT: u32 = 720;
+
+/// Number of entries in a line buffer, addressed by a 12 bit x coordinate
+const LINE_BUFFER_SIZE: usize = 4096;
+/// Number of palette entries: 32 palettes of 16 colours
+pub const PALETTE_SIZE: usize = 512;
+/// Number of sprites in the sprite BRAM
+pub const SPRITE_COUNT: usize = 512;
+/// Number of VRAM words between consecutive texel rows of a texture.
+/// Textures are stored row-interleaved like tiles.hex: row 0 of all 256 tiles,
+/// then row 1 of all tiles, etc, with 2 words (8 texels) per tile row.
+const TEXEL_ROW_STRIDE: u32 = 512;
+/// Bit position for the palette index in a tilemap entry
+const PALETTE_INDEX_SHIFT: u16 = 10;
+/// Mask for 12 bit screen and line buffer coordinates
+const COORD_MASK: u16 = 0xfff;
+
+/// Lines per frame including vertical blanking, with the reduced blanking
+/// used under Verilator. The FPGA has 741.
+pub const FRAME_LINES: u32 = 723;
+
+/// Frames the sprite matcher waits before it starts moving sprites, under
+/// Verilator. Synthesized for the FPGA it waits 480 frames instead.
+pub const VELOCITY_DELAY_FRAMES: u64 = 45;
+
+/// Word address in VRAM that stands in for the RTL's tile map BRAM. Sprite
+/// tilemap addresses are relative to this.
+pub const TILEMAP_VRAM_ADDRESS: u32 = 0;
+/// Word address in VRAM that stands in for the RTL's tile BRAM. Sprite tile
+/// bitmap addresses are relative to this.
+pub const TILES_VRAM_ADDRESS: u32 = 2048;
+
+/// Sign extend a 12 bit coordinate, like the RTL's `$signed()` compares
+fn sign_extend_12(value: u16) -> i16 {
+    ((value << 4) as i16) >> 4
+}
+
+// --- Simple VDP State Simulation ---
+pub struct VdpState {
+    pub width: u32,
+    pub height: u32,
+    /// Represents the VDP's video memory or generated output for a frame
+    pub frame_buffer: Vec<u8>,
+    pub frame_count: u64,
+    /// Line the raster is on, counting into vertical blanking
+    pub line: u32,
+
+    /// Word addressed video memory holding tilemaps and texels
+    pub vram: Vec<u16>,
+    /// 24 bit `rrggbb` colours, indexed by `palette << 4 | texel`
+    pub palette: Vec<u32>,
+    /// Sprites in drawing order, later sprites are drawn over earlier ones
+    pub sprites: Vec<Sprite>,
+
+    /// Number of frames drawn before sprites start moving by their velocity
+    pub velocity_delay_frames: u64,
+    /// Mirror sprites with `x_flip` set. The RTL draw pipeline ignores the
+    /// bit for now, so this is off to show what the FPGA shows.
+    pub future_x_flip: bool,
+
+    /// Cycle budget of the draw domain
+    pub timing: DrawTiming,
+    /// Draw domain usage of each line of the last frame
+    pub line_stats: Vec<LineStats>,
+
+    /// Registers the host CPU programs the VDP through
+    pub host: HostPort,
+    /// Register writes to replay as the raster reaches them
+    pub playback: Option<Player>,
+    /// Register writes made so far, if they are being recorded
+    pub recording: Option<CommandStream>,
+
+    /// 9 bit colour indices for the line being drawn
+    line_buffer: Vec<u16>,
+}
+
+impl VdpState {
+    pub fn new(width: u32, height: u32) -> Self {
+        VdpState {
+            width,
+            height,
+            // Initialize with black pixels
+            frame_buffer: vec![0; (width * height * 4) as usize],
+            frame_count: 0,
+            line: 0,
+
+            vram: vec![0; 4 * 1024 * 1024], // 8MB of VRAM
+            palette: vec![0; PALETTE_SIZE],
+            sprites: Vec::new(),
+            velocity_delay_frames: VELOCITY_DELAY_FRAMES,
+            future_x_flip: false,
+
+            timing: DrawTiming::default(),
+            line_stats: vec![LineStats::default(); height as usize],
+
+            host: HostPort::default(),
+            playback: None,
+            recording: None,
+
+            line_buffer: vec![0; LINE_BUFFER_SIZE],
+        }
+    }
+
+    /// Load a palette.hex file: 512 `rrggbb` colours, 16 per palette
+    pub fn load_palette_hex(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let text = hexfile::read_text(path.as_ref())?;
+        for word in hex_words(path.as_ref(), &text)? {
+            let colour = word.value().map_err(|e| hex_error(path.as_ref(), e))?;
+            if colour > 0xffffff {
+                return Err(load_error(word.address, "colour is wider than 24 bits"));
+            }
+            *self
+                .palette
+                .get_mut(word.address)
+                .ok_or_else(|| load_error(word.address, "past the end of the palette"))? =
+                colour as u32;
+        }
+        Ok(())
+    }
+
+    /// Load a tiles.hex file into the texture the RTL draws from. The file is
+    /// loaded as is, so it keeps its layout of one line per texel row.
+    pub fn load_tiles_hex(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.load_vram_hex(path, TILES_VRAM_ADDRESS)
+    }
+
+    /// Load a tile_map.hex file into the tilemap the RTL draws from
+    pub fn load_tilemap_hex(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.load_vram_hex(path, TILEMAP_VRAM_ADDRESS)
+    }
+
+    /// Load the palette, tiles and tilemap from a binary asset file, to the
+    /// same places as the hex files
+    pub fn load_assets_bin(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let assets = binfile::read(path).map_err(|e| match e {
+            ConversionError::IoError(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        })?;
+        if assets.palette.len() > self.palette.len() {
+            return Err(load_error(
+                self.palette.len(),
+                "past the end of the palette",
+            ));
+        }
+        for (address, &colour) in assets.palette.iter().enumerate() {
+            if colour > 0xffffff {
+                return Err(load_error(address, "colour is wider than 24 bits"));
+            }
+            self.palette[address] = colour;
+        }
+        self.load_vram_words(&assets.tiles, TILES_VRAM_ADDRESS)?;
+        self.load_vram_words(&assets.tilemap, TILEMAP_VRAM_ADDRESS)
+    }
+
+    /// Copy 16 bit words into VRAM starting at the given word address
+    pub fn load_vram_words(&mut self, words: &[u16], address: u32) -> io::Result<()> {
+        let start = address as usize;
+        self.vram
+            .get_mut(start..start + words.len())
+            .ok_or_else(|| load_error(start + words.len(), "past the end of VRAM"))?
+            .copy_from_slice(words);
+        Ok(())
+    }
+
+    /// Load a hex file of 16 bit words into VRAM starting at the given word address
+    pub fn load_vram_hex(&mut self, path: impl AsRef<Path>, address: u32) -> io::Result<()> {
+        let text = hexfile::read_text(path.as_ref())?;
+        for word in hex_words(path.as_ref(), &text)? {
+            let value = word.value().map_err(|e| hex_error(path.as_ref(), e))?;
+            if value > 0xffff {
+                return Err(load_error(word.address, "word is wider than 16 bits"));
+            }
+            let vram_address = address as usize + word.address;
+            *self
+                .vram
+                .get_mut(vram_address)
+                .ok_or_else(|| load_error(vram_address, "past the end of VRAM"))? = value as u16;
+        }
+        Ok(())
+    }
+
+    /// Load a sprites.hex file of 108 or 144 bit sprite BRAM records,
+    /// replacing the sprite list
+    pub fn load_sprites_hex(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let text = hexfile::read_text(path.as_ref())?;
+        let mut sprites = vec![Sprite::default(); SPRITE_COUNT];
+        for word in hex_words(path.as_ref(), &text)? {
+            let fields = word.fields_36(4).map_err(|e| hex_error(path.as_ref(), e))?;
+            *sprites
+                .get_mut(word.address)
+                .ok_or_else(|| load_error(word.address, "past the end of the sprite BRAM"))? =
+                Sprite::from_record(&fields);
+        }
+        self.sprites = sprites;
+        Ok(())
+    }
+
+    /// Run until the end of the current frame, one scan line at a time
+    pub fn update_frame_buffer_data(&mut self) {
+        self.step_line();
+        while self.line != 0 {
+            self.step_line();
+        }
+    }
+
+    /// Draw the line the raster is on, or do the vertical blanking work, and
+    /// move on to the next line
+    pub fn step_line(&mut self) {
+        if let Some(mut player) = self.playback.take() {
+            let (frame, line) = self.raster_position();
+            for command in player.due(frame, line) {
+                self.write_register(command.register, command.value);
+            }
+            self.playback = Some(player);
+        }
+
+        if self.line == 0 {
+            self.frame_count += 1;
+        }
+
+        if self.line < self.height {
+            self.line_stats[self.line as usize] = self.draw_line(self.line);
+            self.scan_out_line(self.line);
+        } else if self.line == self.height && self.frame_count >= self.velocity_delay_frames {
+            // The sprite matcher moves the sprites while matching line 720, so
+            // the frame in which the delay runs out is still drawn unmoved
+            for sprite in self.sprites.iter_mut() {
+                sprite.apply_velocity();
+            }
+        }
+
+        self.host.line_done(self.line);
+        self.line = (self.line + 1) % FRAME_LINES.max(self.height + 1);
+    }
+
+    /// The frame and line the next call to `step_line` draws
+    pub fn raster_position(&self) -> (u64, u32) {
+        if self.line == 0 {
+            (self.frame_count + 1, 0)
+        } else {
+            (self.frame_count, self.line)
+        }
+    }
+
+    /// Add a register write to the recording, if there is one
+    pub(crate) fn record(&mut self, register: Register, value: u16) {
+        let (frame, line) = self.raster_position();
+        if let Some(recording) = &mut self.recording {
+            recording.commands.push(Command {
+                frame,
+                line,
+                register,
+                value,
+            });
+        }
+    }
+
+    /// Draw all sprites intersecting scan line `sy` into the line buffer,
+    /// dropping whatever doesn't fit in the cycle budget
+    fn draw_line(&mut self, sy: u32) -> LineStats {
+        // The line buffer is cleared after each line is sent to the screen
+        self.line_buffer.fill(0);
+
+        let timing = self.timing;
+        let mut stats = LineStats::default();
+        let mut cycle = 0;
+
+        let sy = sy as i16;
+        for sprite in self.sprites.iter() {
+            let y_height = sprite.y_height;
+            let x_width = sprite.x_width;
+            let screen_y = y_height.screen_y() as u16;
+
+            // a tile is 16 lines tall with pixel doubling
+            let sprite_height = (y_height.height() as u16) << 4;
+            let first_y = sign_extend_12(screen_y);
+            let last_y = sign_extend_12(screen_y.wrapping_add(sprite_height) & COORD_MASK);
+            if sy < first_y || sy >= last_y {
+                continue;
+            }
+
+            let line_y = (sy as u16).wrapping_sub(screen_y) & COORD_MASK;
+            let offset_y = if x_width.y_flip() {
+                sprite_height.wrapping_sub(1).wrapping_sub(line_y) & COORD_MASK
+            } else {
+                line_y
+            } as u32;
+
+            // same address math as sprite_matcher.sv
+            let tilemap_y = ((offset_y >> 4) + y_height.tilemap_y()) & COORD_MASK as u32;
+            let entry_address = TILEMAP_VRAM_ADDRESS
+                + (sprite.addr.tilemap_addr() << 9)
+                + (tilemap_y << (y_height.tilemap_size_a() + 4))
+                + (tilemap_y << (y_height.tilemap_size_b() + 4))
+                + x_width.tilemap_x();
+            let row_address = TILES_VRAM_ADDRESS
+                + sprite.addr.tile_bitmap_addr()
+                + ((offset_y >> 1) & 7) * TEXEL_ROW_STRIDE;
+
+            let width = x_width.width();
+            // sprite_matcher.sv passes x_flip on but the draw pipeline doesn't mirror yet
+            let x_flip = self.future_x_flip && x_width.x_flip();
+            stats.sprites += 1;
+            stats.tiles += width;
+            cycle += timing.cycles_per_sprite;
+            let mut truncated = false;
+
+            let mut lb_x = x_width.screen_x() as u16;
+            for tile in 0..width {
+                cycle += timing.memory_latency;
+                let tile = if x_flip { width - 1 - tile } else { tile };
+                let entry = self.vram_word(entry_address + tile);
+                let palette_index = (entry >> PALETTE_INDEX_SHIFT) & 0x1f;
+                let tile_index = (entry & 0x3ff) as u32;
+
+                // one cycle per 4 texels
+                for half in 0..2 {
+                    let fits = timing.fits(cycle);
+                    truncated |= !fits;
+                    cycle += 1;
+
+                    let half = if x_flip { 1 - half } else { half };
+                    let texels = self.vram_word(row_address + (tile_index << 1 | half));
+                    for i in 0..4 {
+                        let i = if x_flip { 3 - i } else { i };
+                        let colour = (palette_index << 4) | ((texels >> (i * 4)) & 0xf);
+
+                        // every texel is doubled horizontally
+                        for _ in 0..2 {
+                            if fits {
+                                self.line_buffer[lb_x as usize] = colour;
+                            }
+                            lb_x = (lb_x + 1) & COORD_MASK;
+                        }
+                    }
+                }
+            }
+
+            if truncated {
+                stats.truncated_sprites += 1;
+            }
+        }
+
+        stats.cycles = cycle;
+        stats
+    }
+
+    /// Look up the colour of each visible line buffer entry and write it to the frame buffer
+    fn scan_out_line(&mut self, y: u32) {
+        let row_offset = (y * (self.width << 2)) as usize;
+        for x in 0..self.width as usize {
+            let rgb = self.palette[self.line_buffer[x] as usize];
+            let index = row_offset + (x << 2);
+            self.frame_buffer[index] = (rgb >> 16) as u8;
+            self.frame_buffer[index + 1] = (rgb >> 8) as u8;
+            self.frame_buffer[index + 2] = rgb as u8;
+            self.frame_buffer[index + 3] = 255;
+        }
+    }
+
+    /// Read a word of VRAM, wrapping addresses around the end of memory
+    fn vram_word(&self, address: u32) -> u16 {
+        self.vram[address as usize % self.vram.len()]
+    }
+}
+
+/// Split the text of a hex file into words, naming the file in errors
+fn hex_words<'a>(path: &Path, text: &'a str) -> io::Result<Vec<hexfile::HexWord<'a>>> {
+    hexfile::words(text).map_err(|e| hex_error(path, e))
+}
+
+fn hex_error(path: &Path, error: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {error}", path.display()),
+    )
+}
+
+fn load_error(address: usize, error: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("address {address:#x}: {error}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A VDP with tile 0 in palette 1 at the top left of the tilemap. Row 0
+    /// of the tile has texels 1 to 8 and row 7 has texels 9 to 16 (0).
+    fn vdp() -> VdpState {
+        let mut vdp = VdpState::new(64, 32);
+        vdp.vram[TILEMAP_VRAM_ADDRESS as usize] = 1 << PALETTE_INDEX_SHIFT;
+        let row = |row: u32| (TILES_VRAM_ADDRESS + row * TEXEL_ROW_STRIDE) as usize;
+        vdp.vram[row(0)..row(0) + 2].copy_from_slice(&[0x4321, 0x8765]);
+        vdp.vram[row(7)..row(7) + 2].copy_from_slice(&[0xcba9, 0x0fed]);
+        vdp
+    }
+
+    /// A one tile sprite at the given position
+    fn sprite(x: u32, y: u32) -> Sprite {
+        let mut sprite = Sprite::default();
+        sprite.y_height.set_height(1);
+        sprite.y_height.set_screen_y(y);
+        sprite.x_width.set_width(1);
+        sprite.x_width.set_screen_x(x);
+        sprite
+    }
+
+    /// The line buffer from `x` on, as texels of palette 1
+    fn texels(vdp: &VdpState, x: usize, len: usize) -> Vec<u16> {
+        vdp.line_buffer[x..x + len]
+            .iter()
+            .map(|&colour| colour ^ 0x10)
+            .collect()
+    }
+
+    #[test]
+    fn sprites_draw_doubled_texels_through_their_palette() {
+        let mut vdp = vdp();
+        vdp.sprites = vec![sprite(4, 2)];
+
+        let stats = vdp.draw_line(2);
+        assert_eq!(
+            texels(&vdp, 4, 16),
+            [1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8]
+        );
+        assert_eq!(vdp.line_buffer[3], 0);
+        assert_eq!(vdp.line_buffer[20], 0);
+        assert_eq!((stats.sprites, stats.tiles), (1, 1));
+
+        // 16 lines, each texel row drawn twice
+        vdp.draw_line(17);
+        assert_eq!(texels(&vdp, 4, 4), [9, 9, 10, 10]);
+        assert_eq!(vdp.draw_line(1).sprites, 0);
+        assert_eq!(vdp.draw_line(18).sprites, 0);
+    }
+
+    #[test]
+    fn sprites_above_the_screen_start_part_way_down() {
+        let mut vdp = vdp();
+        // y = -14, so line 0 is the sprite's line 14, texel row 7
+        vdp.sprites = vec![sprite(0, 0xff2)];
+        vdp.draw_line(0);
+        assert_eq!(texels(&vdp, 0, 2), [9, 9]);
+        assert_eq!(vdp.draw_line(2).sprites, 0);
+    }
+
+    #[test]
+    fn y_flip_mirrors_the_rows() {
+        let mut vdp = vdp();
+        let mut flipped = sprite(0, 0);
+        flipped.x_width.set_y_flip(true);
+        vdp.sprites = vec![flipped];
+        vdp.draw_line(0);
+        assert_eq!(texels(&vdp, 0, 4), [9, 9, 10, 10]);
+    }
+
+    #[test]
+    fn x_flip_only_mirrors_ahead_of_the_rtl() {
+        let mut vdp = vdp();
+        let mut flipped = sprite(0, 0);
+        flipped.x_width.set_x_flip(true);
+        vdp.sprites = vec![flipped];
+
+        vdp.draw_line(0);
+        assert_eq!(texels(&vdp, 0, 4), [1, 1, 2, 2]);
+
+        vdp.future_x_flip = true;
+        vdp.draw_line(0);
+        assert_eq!(texels(&vdp, 0, 4), [8, 8, 7, 7]);
+        assert_eq!(texels(&vdp, 14, 2), [1, 1]);
+    }
+
+    #[test]
+    fn sprites_past_the_cycle_budget_are_cut_short() {
+        let mut vdp = vdp();
+        vdp.sprites = vec![sprite(0, 0), sprite(16, 0), sprite(32, 0)];
+        vdp.timing.cycles_per_line = 13;
+
+        // Each sprite takes 2 cycles of overhead and 2 fetches, and a fetch
+        // needs 5 more cycles to reach the line buffer
+        let stats = vdp.draw_line(0);
+        assert_eq!(stats.cycles, 12);
+        assert_eq!(stats.truncated_sprites, 1);
+        assert_eq!(texels(&vdp, 30, 2), [8, 8]);
+        assert_eq!(vdp.line_buffer[32], 0);
+
+        vdp.timing.cycles_per_line = 12;
+        let stats = vdp.draw_line(0);
+        assert_eq!(stats.truncated_sprites, 2);
+        assert_eq!(texels(&vdp, 22, 2), [4, 4]);
+        assert_eq!(vdp.line_buffer[24], 0);
+    }
+
+    /// Write a file to a scratch directory for this test run
+    fn scratch_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("vdpemu-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn hex_files_load_where_the_rtl_reads_them() {
+        let mut vdp = VdpState::new(64, 32);
+        vdp.load_palette_hex(scratch_file("palette.hex", b"000000 123456\n@1f ffffff\n"))
+            .unwrap();
+        assert_eq!(vdp.palette[..2], [0, 0x12_3456]);
+        assert_eq!(vdp.palette[0x1f], 0xff_ffff);
+
+        vdp.load_tiles_hex(scratch_file("tiles.hex", b"4321 8765\n"))
+            .unwrap();
+        vdp.load_tilemap_hex(scratch_file("tile_map.hex", b"// top left\n0401\n"))
+            .unwrap();
+        assert_eq!(vdp.vram[TILES_VRAM_ADDRESS as usize + 1], 0x8765);
+        assert_eq!(vdp.vram[TILEMAP_VRAM_ADDRESS as usize], 0x0401);
+    }
+
+    #[test]
+    fn sprite_records_load_with_or_without_velocity() {
+        let mut vdp = VdpState::new(64, 32);
+        let records = "000000004000000003000000002000000001\n000000003000000002000000001\n";
+        vdp.load_sprites_hex(scratch_file("sprites.hex", records.as_bytes()))
+            .unwrap();
+        assert_eq!(vdp.sprites.len(), SPRITE_COUNT);
+        assert_eq!(vdp.sprites[0], Sprite::from_record(&[1, 2, 3, 4]));
+        assert_eq!(vdp.sprites[1], Sprite::from_record(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn bad_hex_files_are_rejected() {
+        let mut vdp = VdpState::new(64, 32);
+        let error = vdp
+            .load_palette_hex(scratch_file("wide.hex", b"1000000\n"))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "address 0x0: colour is wider than 24 bits"
+        );
+        assert!(vdp
+            .load_palette_hex(scratch_file("long.hex", b"@200 1\n"))
+            .is_err());
+        assert!(vdp
+            .load_sprites_hex(scratch_file("many.hex", b"@200 1\n"))
+            .is_err());
+        assert!(vdp
+            .load_tiles_hex(scratch_file("bad_tiles.hex", b"zz\n"))
+            .unwrap_err()
+            .to_string()
+            .ends_with("line 1: bad word zz: invalid digit found in string"));
+    }
+
+    #[test]
+    fn binary_assets_load_like_the_hex_files() {
+        let assets = binfile::BinaryAssets {
+            header: binfile::AssetHeader {
+                version: binfile::VERSION,
+                tile_width: 8,
+                tile_height: 8,
+                tilemap_width: 32,
+                tilemap_height: 1,
+                palettes: 1,
+                colors_per_palette: 2,
+                tile_slots: 256,
+                unique_tiles: 1,
+                palette_words: 2,
+                tile_words: 2,
+                tilemap_words: 1,
+                mask_words: 0,
+            },
+            palette: vec![0, 0x12_3456],
+            tiles: vec![0x4321, 0x8765],
+            tilemap: vec![0x0401],
+            mask: Vec::new(),
+        };
+
+        let mut vdp = VdpState::new(64, 32);
+        vdp.load_assets_bin(scratch_file("assets.bin", &assets.to_bytes()))
+            .unwrap();
+        assert_eq!(vdp.palette[..2], [0, 0x12_3456]);
+        assert_eq!(vdp.vram[TILES_VRAM_ADDRESS as usize + 1], 0x8765);
+        assert_eq!(vdp.vram[TILEMAP_VRAM_ADDRESS as usize], 0x0401);
+
+        let error = vdp
+            .load_assets_bin(scratch_file("short.bin", &assets.to_bytes()[..41]))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}